@@ -0,0 +1,344 @@
+//! A computer opponent based on a negamax search with alpha-beta pruning.
+
+use crate::board::{Board, GameCell};
+use crate::solver::{self, Position};
+use rand::{seq::SliceRandom, Rng};
+use std::time::{Duration, Instant};
+
+/// Score of a position where the player to move has already won. Wins found sooner score higher.
+/// Scores more than half of this in either direction are forced wins or losses.
+pub const WIN_SCORE: i32 = 1_000_000;
+
+/// How often the search checks whether it has run out of time, in nodes
+const DEADLINE_CHECK_INTERVAL: u64 = 4096;
+
+/// How strongly the computer plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+    Perfect,
+}
+
+impl Difficulty {
+    /// Maximum number of plies to look ahead
+    fn max_depth(self) -> u32 {
+        match self {
+            Difficulty::Easy => 2,
+            Difficulty::Medium => 4,
+            Difficulty::Hard => 8,
+            Difficulty::Perfect => u32::MAX,
+        }
+    }
+
+    /// Chance of playing a random move instead of searching
+    fn mistake_chance(self) -> f64 {
+        match self {
+            Difficulty::Easy => 0.3,
+            Difficulty::Medium => 0.1,
+            Difficulty::Hard | Difficulty::Perfect => 0.0,
+        }
+    }
+
+    /// How long the computer may think for each move
+    fn time_budget(self) -> Duration {
+        match self {
+            Difficulty::Easy | Difficulty::Medium => Duration::from_secs(1),
+            Difficulty::Hard => Duration::from_secs(3),
+            Difficulty::Perfect => Duration::from_secs(10),
+        }
+    }
+}
+
+impl std::fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Medium => "Medium",
+            Difficulty::Hard => "Hard",
+            Difficulty::Perfect => "Perfect",
+        })
+    }
+}
+
+impl std::str::FromStr for Difficulty {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "easy" => Ok(Difficulty::Easy),
+            "medium" => Ok(Difficulty::Medium),
+            "hard" => Ok(Difficulty::Hard),
+            "perfect" => Ok(Difficulty::Perfect),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Picks a column for `color` to play in at the given difficulty, or `None` if the board is full
+///
+/// This blocks for up to the difficulty's time budget.
+pub fn choose_move(board: &Board, color: GameCell, win_length: usize, difficulty: Difficulty) -> Option<usize> {
+    choose_move_within(board, color, win_length, difficulty, difficulty.time_budget())
+}
+
+/// Like `choose_move`, but thinks for no longer than `time_limit` even if the difficulty's time
+/// budget is longer
+pub fn choose_move_within(
+    board: &Board,
+    color: GameCell,
+    win_length: usize,
+    difficulty: Difficulty,
+    time_limit: Duration,
+) -> Option<usize> {
+    let moves = move_order(board);
+    let mut rng = rand::thread_rng();
+    if rng.gen_bool(difficulty.mistake_chance()) {
+        return moves.choose(&mut rng).copied();
+    }
+
+    let start = Instant::now();
+    let budget = difficulty.time_budget().min(time_limit);
+    let deadline = start + budget;
+
+    // Play perfectly if we can, falling back to a regular search if the solver runs out of time
+    if difficulty == Difficulty::Perfect && solver::supports(board, win_length) {
+        let best = solver::with_solver(|solver| {
+            solver.set_deadline(Some(start + budget * 3 / 4));
+            Position::from_board(board, color).and_then(|position| solver.best_move(&position))
+        });
+        if let Some((column, _)) = best {
+            return Some(column);
+        }
+    }
+
+    let empty_cells = (0..board.width())
+        .map(|c| board.height() - board.column_height(c))
+        .sum::<usize>() as u32;
+    let max_depth = difficulty.max_depth().min(empty_cells).max(1);
+
+    // Search deeper and deeper until we run out of depth or time, keeping the last complete result
+    let mut searcher = Searcher::new(win_length, Some(deadline));
+    let mut best = moves.first().copied();
+    for depth in 1..=max_depth {
+        match searcher.search_root(board, color, depth) {
+            Some((column, score)) => {
+                best = column;
+                // No point searching deeper once the outcome is decided
+                if score.abs() > WIN_SCORE / 2 {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    best
+}
+
+/// Scores every column `color` could play in, searching as deeply as possible within `time_budget`.
+/// Scores are from the point of view of `color`; see `WIN_SCORE`.
+pub fn score_moves(board: &Board, color: GameCell, win_length: usize, time_budget: Duration) -> Vec<(usize, i32)> {
+    let deadline = Instant::now() + time_budget;
+    let empty_cells = (0..board.width())
+        .map(|c| board.height() - board.column_height(c))
+        .sum::<usize>() as u32;
+
+    let mut searcher = Searcher::new(win_length, Some(deadline));
+    let mut scores = Vec::new();
+    for depth in 1..=empty_cells.max(1) {
+        // Search each move with a full window so every score is exact, not just the best one
+        let result = move_order(board)
+            .into_iter()
+            .map(|column| {
+                let mut next = *board;
+                let row = next.drop_piece(column, color).unwrap();
+                let score = if next.lines_through(column, row, win_length) != 0 {
+                    WIN_SCORE
+                } else {
+                    -searcher.negamax(&next, color.other(), depth - 1, -WIN_SCORE - 1, WIN_SCORE + 1, 1)?
+                };
+                Some((column, score))
+            })
+            .collect::<Option<Vec<_>>>();
+
+        match result {
+            Some(result) => {
+                scores = result;
+                if scores.iter().all(|&(_, score)| score.abs() > WIN_SCORE / 2) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    scores
+}
+
+/// Picks a column for `color` to play in by searching exactly `depth` plies, or `None` if the board
+/// is full
+#[cfg(test)]
+pub fn best_move(board: &Board, color: GameCell, win_length: usize, depth: u32) -> Option<usize> {
+    Searcher::new(win_length, None)
+        .search_root(board, color, depth)
+        .and_then(|(column, _)| column)
+}
+
+struct Searcher {
+    win_length: usize,
+    /// When to give up searching, if ever
+    deadline: Option<Instant>,
+    nodes: u64,
+}
+
+impl Searcher {
+    fn new(win_length: usize, deadline: Option<Instant>) -> Self {
+        Searcher {
+            win_length,
+            deadline,
+            nodes: 0,
+        }
+    }
+
+    /// Returns the best column and its score, or `None` if time ran out
+    fn search_root(&mut self, board: &Board, color: GameCell, depth: u32) -> Option<(Option<usize>, i32)> {
+        let mut best = None;
+        let mut alpha = -WIN_SCORE - 1;
+        let beta = WIN_SCORE + 1;
+
+        for column in move_order(board) {
+            let mut next = *board;
+            let row = next.drop_piece(column, color).unwrap();
+            let score = if next.lines_through(column, row, self.win_length) != 0 {
+                WIN_SCORE
+            } else {
+                -self.negamax(&next, color.other(), depth.saturating_sub(1), -beta, -alpha, 1)?
+            };
+
+            if best.is_none() || score > alpha {
+                alpha = score;
+                best = Some(column);
+            }
+        }
+
+        Some((best, alpha))
+    }
+
+    /// Returns the score of `board` from the point of view of `color`, who is about to move, or
+    /// `None` if time ran out
+    fn negamax(&mut self, board: &Board, color: GameCell, depth: u32, mut alpha: i32, beta: i32, ply: i32) -> Option<i32> {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(DEADLINE_CHECK_INTERVAL) {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return None;
+                }
+            }
+        }
+
+        let moves = move_order(board);
+        if moves.is_empty() {
+            return Some(0);
+        }
+
+        // Take an immediate win before searching anything else
+        for &column in &moves {
+            let mut next = *board;
+            let row = next.drop_piece(column, color).unwrap();
+            if next.lines_through(column, row, self.win_length) != 0 {
+                return Some(WIN_SCORE - ply);
+            }
+        }
+
+        if depth == 0 {
+            return Some(evaluate(board, color, self.win_length));
+        }
+
+        let mut best = -WIN_SCORE;
+        for column in moves {
+            let mut next = *board;
+            next.drop_piece(column, color);
+            let score = -self.negamax(&next, color.other(), depth - 1, -beta, -alpha, ply + 1)?;
+
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+/// Estimates how good a position is for `color` by comparing the number of cells each player
+/// could complete a line on
+fn evaluate(board: &Board, color: GameCell, win_length: usize) -> i32 {
+    let ours = board.winning_cells(color, win_length).count_ones() as i32;
+    let theirs = board.winning_cells(color.other(), win_length).count_ones() as i32;
+    ours - theirs
+}
+
+/// Returns the columns that can be played, from the centre outwards
+fn move_order(board: &Board) -> Vec<usize> {
+    let width = board.width();
+    let mut columns = (0..width)
+        .filter(|&c| !board.is_column_full(c))
+        .collect::<Vec<_>>();
+    columns.sort_by_key(|&c| (2 * c as isize - (width as isize - 1)).abs());
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEARCH_DEPTH: u32 = 7;
+
+    #[test]
+    fn takes_immediate_win() {
+        let mut board = Board::new(7, 6);
+        for c in 1..4 {
+            board.drop_piece(c, GameCell::Red);
+            board.drop_piece(c, GameCell::Yellow);
+        }
+        let column = best_move(&board, GameCell::Red, 4, SEARCH_DEPTH).unwrap();
+        assert!(column == 0 || column == 4);
+    }
+
+    #[test]
+    fn blocks_opponent_win() {
+        let mut board = Board::new(7, 6);
+        for _ in 0..3 {
+            board.drop_piece(6, GameCell::Red);
+        }
+        board.drop_piece(0, GameCell::Yellow);
+        board.drop_piece(1, GameCell::Yellow);
+        assert_eq!(best_move(&board, GameCell::Yellow, 4, SEARCH_DEPTH), Some(6));
+        assert_eq!(choose_move(&board, GameCell::Yellow, 4, Difficulty::Hard), Some(6));
+    }
+
+    #[test]
+    fn full_board_has_no_moves() {
+        let mut board = Board::new(4, 4);
+        for c in 0..4 {
+            for r in 0..4 {
+                let cell = if (c / 2 + r) % 2 == 0 { GameCell::Red } else { GameCell::Yellow };
+                board.drop_piece(c, cell);
+            }
+        }
+        assert_eq!(best_move(&board, GameCell::Red, 4, SEARCH_DEPTH), None);
+        assert_eq!(choose_move(&board, GameCell::Red, 4, Difficulty::Easy), None);
+    }
+
+    #[test]
+    fn stops_thinking_at_time_limit() {
+        let board = Board::new(9, 7);
+        let start = Instant::now();
+        let column = choose_move_within(&board, GameCell::Red, 4, Difficulty::Perfect, Duration::from_millis(100));
+        assert!(column.is_some());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,515 @@
+//! A bitboard representation of the game grid.
+//!
+//! Each column takes up `height + 1` consecutive bits, starting from the bottom row, with the extra
+//! bit acting as a separator so that lines can't wrap from the top of one column into the next.
+//! Cell `(column, row)` lives at bit `column * (height + 1) + row`. This needs at most
+//! `MAX_BOARD_SIZE * (MAX_BOARD_SIZE + 1)` bits, which is why masks are `u128`s.
+//!
+//! On toroidal boards the columns form a ring, so shifting a mask past the last column carries
+//! on from the first. The separator bits still stop lines from wrapping vertically.
+
+/// Largest number of rows or columns a board can have
+pub const MAX_BOARD_SIZE: usize = 9;
+/// Smallest number of rows or columns a game may be played with
+pub const MIN_BOARD_SIZE: usize = 4;
+
+const RED_PIECE: char = '🔴';
+const YELLOW_PIECE: char = '🟡';
+const GREEN_PIECE: char = '🟢';
+const BLUE_PIECE: char = '🔵';
+const BLANK_CELL: char = '⚫';
+
+/// A bit set with one bit per cell of a board
+pub type Bitmask = u128;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GameCell {
+    Empty,
+    Red,
+    Yellow,
+    Green,
+    Blue,
+}
+
+/// Colours in the order players are assigned them
+pub const PLAYER_COLORS: [GameCell; 4] = [GameCell::Red, GameCell::Yellow, GameCell::Green, GameCell::Blue];
+
+impl GameCell {
+    /// Returns the colour of the opponent in a two-player game, or `Empty` for cells that don't
+    /// belong to either of its players
+    pub fn other(self) -> GameCell {
+        match self {
+            GameCell::Red => GameCell::Yellow,
+            GameCell::Yellow => GameCell::Red,
+            GameCell::Green | GameCell::Blue | GameCell::Empty => GameCell::Empty,
+        }
+    }
+
+    /// The name of this colour in plain text
+    pub fn name(self) -> &'static str {
+        match self {
+            GameCell::Empty => "Empty",
+            GameCell::Red => "Red",
+            GameCell::Yellow => "Yellow",
+            GameCell::Green => "Green",
+            GameCell::Blue => "Blue",
+        }
+    }
+}
+
+impl std::fmt::Display for GameCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            GameCell::Empty => BLANK_CELL,
+            GameCell::Red => RED_PIECE,
+            GameCell::Yellow => YELLOW_PIECE,
+            GameCell::Green => GREEN_PIECE,
+            GameCell::Blue => BLUE_PIECE,
+        })
+    }
+}
+
+/// The grid of cells a game is played on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Board {
+    width: usize,
+    height: usize,
+    /// Pieces belonging to each colour, in the order of `PLAYER_COLORS`
+    masks: [Bitmask; 4],
+    /// Index of the first empty row above the topmost piece of each column
+    heights: [u8; MAX_BOARD_SIZE],
+    /// Whether lines wrap around from the last column to the first
+    wraps: bool,
+}
+
+impl Board {
+    /// # Panic
+    /// Panics if either dimension is larger than `MAX_BOARD_SIZE`
+    pub fn new(width: usize, height: usize) -> Self {
+        assert!(width <= MAX_BOARD_SIZE && height <= MAX_BOARD_SIZE, "board too large");
+        Board {
+            width,
+            height,
+            masks: [0; 4],
+            heights: [0; MAX_BOARD_SIZE],
+            wraps: false,
+        }
+    }
+
+    /// Creates a board where horizontal and diagonal lines wrap around from the last column to
+    /// the first
+    ///
+    /// # Panic
+    /// Panics if either dimension is larger than `MAX_BOARD_SIZE`
+    pub fn toroidal(width: usize, height: usize) -> Self {
+        Board {
+            wraps: true,
+            ..Board::new(width, height)
+        }
+    }
+
+    pub fn wraps(&self) -> bool {
+        self.wraps
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the row the next piece dropped into `column` would land on
+    ///
+    /// # Panic
+    /// Panics if `column` is out of bounds
+    pub fn column_height(&self, column: usize) -> usize {
+        assert!(column < self.width, "column out of bounds");
+        self.heights[column] as usize
+    }
+
+    /// # Panic
+    /// Panics if the cell is out of bounds
+    pub fn get(&self, column: usize, row: usize) -> GameCell {
+        self.try_get(column, row).expect("cell out of bounds")
+    }
+
+    /// Returns `None` if the cell is out of bounds
+    pub fn try_get(&self, column: usize, row: usize) -> Option<GameCell> {
+        if column >= self.width || row >= self.height {
+            return None;
+        }
+
+        let bit = self.bit(column, row);
+        Some(
+            PLAYER_COLORS
+                .iter()
+                .zip(&self.masks)
+                .find(|&(_, mask)| mask & bit != 0)
+                .map_or(GameCell::Empty, |(&color, _)| color),
+        )
+    }
+
+    /// Overwrites a single cell, regardless of whether there is anything below it
+    ///
+    /// # Panic
+    /// Panics if the cell is out of bounds
+    #[cfg(test)]
+    pub fn set(&mut self, column: usize, row: usize, cell: GameCell) {
+        assert!(column < self.width && row < self.height, "cell out of bounds");
+
+        let bit = self.bit(column, row);
+        for mask in &mut self.masks {
+            *mask &= !bit;
+        }
+        if let Some(i) = Self::mask_index(cell) {
+            self.masks[i] |= bit;
+        }
+
+        // Recompute the height from the topmost piece in the column
+        let column_mask = self.column_mask(column) & self.occupied();
+        self.heights[column] = if column_mask == 0 {
+            0
+        } else {
+            (Bitmask::BITS - column_mask.leading_zeros() - self.bit_index(column, 0)) as u8
+        };
+    }
+
+    /// Returns the cells occupied by `cell`, laid out as described in the module documentation
+    pub fn pieces(&self, cell: GameCell) -> Bitmask {
+        match Self::mask_index(cell) {
+            Some(i) => self.masks[i],
+            None => self.playable_mask() & !self.occupied(),
+        }
+    }
+
+    /// Drops a piece into `column`, returning the row it landed on, or `None` if the column is full
+    ///
+    /// # Panic
+    /// Panics if `column` is out of bounds or `cell` is `GameCell::Empty`
+    pub fn drop_piece(&mut self, column: usize, cell: GameCell) -> Option<usize> {
+        let row = self.column_height(column);
+        if row >= self.height {
+            return None;
+        }
+
+        let i = Self::mask_index(cell).expect("cannot drop an empty cell");
+        self.masks[i] |= self.bit(column, row);
+        self.heights[column] += 1;
+        Some(row)
+    }
+
+    /// Places a piece in an empty cell, whether or not there is anything below it. Returns false if
+    /// the cell is already taken.
+    ///
+    /// # Panic
+    /// Panics if the cell is out of bounds or `cell` is `GameCell::Empty`
+    pub fn place_piece(&mut self, column: usize, row: usize, cell: GameCell) -> bool {
+        if self.get(column, row) != GameCell::Empty {
+            return false;
+        }
+
+        let i = Self::mask_index(cell).expect("cannot place an empty cell");
+        self.masks[i] |= self.bit(column, row);
+        self.heights[column] = self.heights[column].max(row as u8 + 1);
+        true
+    }
+
+    /// Removes the bottom piece of `column`, moving every piece above it down a row. Returns the
+    /// removed piece, or `None` if the column is empty.
+    ///
+    /// # Panic
+    /// Panics if `column` is out of bounds
+    pub fn pop_piece(&mut self, column: usize) -> Option<GameCell> {
+        if self.column_height(column) == 0 {
+            return None;
+        }
+
+        let cell = self.get(column, 0);
+        let column_mask = self.column_mask(column);
+        for mask in &mut self.masks {
+            let shifted = ((*mask & column_mask) >> 1) & column_mask;
+            *mask = (*mask & !column_mask) | shifted;
+        }
+        self.heights[column] -= 1;
+        Some(cell)
+    }
+
+    /// Returns true if no more pieces can be dropped into `column`
+    ///
+    /// # Panic
+    /// Panics if `column` is out of bounds
+    pub fn is_column_full(&self, column: usize) -> bool {
+        self.column_height(column) >= self.height
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.occupied() == self.playable_mask()
+    }
+
+    /// Returns the cells that are part of a line of at least `length` pieces running through
+    /// `(column, row)`, or 0 if there is no such line
+    ///
+    /// # Panic
+    /// Panics if the cell is out of bounds
+    pub fn lines_through(&self, column: usize, row: usize, length: usize) -> Bitmask {
+        let i = match Self::mask_index(self.get(column, row)) {
+            Some(i) => i,
+            None => return 0,
+        };
+        let pieces = self.masks[i];
+        let cell = self.bit(column, row);
+
+        let mut lines = 0;
+        for &shift in &self.directions() {
+            let covered = self.lines(pieces, shift, length);
+            if covered & cell != 0 {
+                lines |= covered;
+            }
+        }
+        lines
+    }
+
+    /// Returns every cell that is part of a line of at least `length` pieces belonging to `cell`,
+    /// or 0 if there are no such lines
+    pub fn lines_of(&self, cell: GameCell, length: usize) -> Bitmask {
+        let pieces = match Self::mask_index(cell) {
+            Some(i) => self.masks[i],
+            None => return 0,
+        };
+        self.directions()
+            .iter()
+            .fold(0, |lines, &shift| lines | self.lines(pieces, shift, length))
+    }
+
+    /// Returns the empty cells which would complete a line of at least `length` pieces for `cell`
+    /// if it were placed there, whether or not they can currently be played
+    pub fn winning_cells(&self, cell: GameCell, length: usize) -> Bitmask {
+        let pieces = match Self::mask_index(cell) {
+            Some(i) => self.masks[i],
+            None => return 0,
+        };
+
+        let mut cells = 0;
+        for &shift in &self.directions() {
+            // The empty cell may be at any position `k` within the line
+            for k in 0..length as i32 {
+                let mut candidates = !0;
+                for i in (0..length as i32).filter(|&i| i != k) {
+                    let offset = (i - k) * shift as i32;
+                    candidates &= if offset > 0 {
+                        self.shift_down(pieces, offset as u32)
+                    } else {
+                        self.shift_up(pieces, -offset as u32)
+                    };
+                }
+                cells |= candidates;
+            }
+        }
+
+        cells & self.playable_mask() & !self.occupied()
+    }
+
+    /// Bit distances between neighbouring cells: vertical, horizontal and both diagonals
+    fn directions(&self) -> [u32; 4] {
+        let stride = self.height as u32 + 1;
+        [1, stride, stride + 1, stride - 1]
+    }
+
+    /// Returns every cell in `pieces` that is part of a line of at least `length` in the direction
+    /// given by `shift`
+    fn lines(&self, pieces: Bitmask, shift: u32, length: usize) -> Bitmask {
+        // Bits which are the lowest cell of a line of `length`
+        let mut starts = pieces;
+        for i in 1..length as u32 {
+            starts &= self.shift_down(pieces, i * shift);
+        }
+        if starts == 0 {
+            return 0;
+        }
+
+        let mut covered = starts;
+        for i in 1..length as u32 {
+            covered |= self.shift_up(starts, i * shift);
+        }
+        covered
+    }
+
+    /// Moves every bit of `mask` down by `n`, carrying bits from the first column over to the last
+    /// on toroidal boards
+    fn shift_down(&self, mask: Bitmask, n: u32) -> Bitmask {
+        if !self.wraps {
+            return mask >> n;
+        }
+        let (bits, n) = self.ring(n);
+        ((mask >> n) | (mask << (bits - n))) & ((1 << bits) - 1)
+    }
+
+    /// Moves every bit of `mask` up by `n`, carrying bits from the last column over to the first
+    /// on toroidal boards
+    fn shift_up(&self, mask: Bitmask, n: u32) -> Bitmask {
+        if !self.wraps {
+            return mask << n;
+        }
+        let (bits, n) = self.ring(n);
+        ((mask << n) | (mask >> (bits - n))) & ((1 << bits) - 1)
+    }
+
+    /// Returns the number of bits the columns take up, and `n` reduced to less than that
+    fn ring(&self, n: u32) -> (u32, u32) {
+        let bits = self.bit_index(self.width, 0);
+        (bits, n % bits)
+    }
+
+    /// Every cell with a piece in it
+    fn occupied(&self) -> Bitmask {
+        self.masks.iter().fold(0, |occupied, mask| occupied | mask)
+    }
+
+    /// Every cell on the board
+    fn playable_mask(&self) -> Bitmask {
+        (0..self.width).fold(0, |mask, c| mask | self.column_mask(c))
+    }
+
+    fn mask_index(cell: GameCell) -> Option<usize> {
+        PLAYER_COLORS.iter().position(|&c| c == cell)
+    }
+
+    fn bit_index(&self, column: usize, row: usize) -> u32 {
+        (column * (self.height + 1) + row) as u32
+    }
+
+    /// The bit standing for `(column, row)` in the masks returned by the board
+    pub fn bit(&self, column: usize, row: usize) -> Bitmask {
+        1 << self.bit_index(column, row)
+    }
+
+    /// All playable cells of a column
+    fn column_mask(&self, column: usize) -> Bitmask {
+        ((1 << self.height) - 1) << self.bit_index(column, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_updates_column_height() {
+        let mut board = Board::new(7, 6);
+        board.set(2, 3, GameCell::Red);
+        assert_eq!(board.column_height(2), 4);
+        assert_eq!(board.drop_piece(2, GameCell::Yellow), Some(4));
+        board.set(2, 4, GameCell::Empty);
+        board.set(2, 3, GameCell::Empty);
+        assert_eq!(board.column_height(2), 0);
+    }
+
+    #[test]
+    fn lines_do_not_wrap_between_columns() {
+        for &(width, height) in &[(7, 6), (9, 9), (4, 4)] {
+            let mut board = Board::new(width, height);
+            // Top of one column followed by the bottom of the next
+            board.set(0, height - 2, GameCell::Red);
+            board.set(0, height - 1, GameCell::Red);
+            board.set(1, 0, GameCell::Red);
+            board.set(1, 1, GameCell::Red);
+            for &(c, r) in &[(0, height - 2), (0, height - 1), (1, 0), (1, 1)] {
+                assert_eq!(board.lines_through(c, r, 4), 0);
+            }
+
+            // Diagonals running off the top or bottom of the board
+            let mut board = Board::new(width, height);
+            board.set(0, 1, GameCell::Yellow);
+            board.set(1, 0, GameCell::Yellow);
+            board.set(2, height - 1, GameCell::Yellow);
+            board.set(3, height - 2, GameCell::Yellow);
+            assert_eq!(board.lines_through(1, 0, 4), 0);
+        }
+    }
+
+    #[test]
+    fn pop_shifts_column_down() {
+        let mut board = Board::new(7, 6);
+        board.drop_piece(3, GameCell::Red);
+        board.drop_piece(3, GameCell::Yellow);
+        board.drop_piece(3, GameCell::Red);
+        board.drop_piece(2, GameCell::Yellow);
+        board.drop_piece(4, GameCell::Yellow);
+
+        assert_eq!(board.pop_piece(3), Some(GameCell::Red));
+        assert_eq!(board.get(3, 0), GameCell::Yellow);
+        assert_eq!(board.get(3, 1), GameCell::Red);
+        assert_eq!(board.get(3, 2), GameCell::Empty);
+        assert_eq!(board.column_height(3), 2);
+        // Neighbouring columns are untouched
+        assert_eq!(board.get(2, 0), GameCell::Yellow);
+        assert_eq!(board.get(4, 0), GameCell::Yellow);
+        // The pop completed a line along the bottom row
+        assert_ne!(board.lines_of(GameCell::Yellow, 3), 0);
+
+        assert_eq!(board.pop_piece(0), None);
+    }
+
+    #[test]
+    fn placed_pieces_leave_gaps() {
+        let mut board = Board::new(4, 4);
+        assert!(board.place_piece(1, 2, GameCell::Red));
+        assert!(!board.place_piece(1, 2, GameCell::Yellow));
+        assert_eq!(board.get(1, 0), GameCell::Empty);
+        assert_eq!(board.column_height(1), 3);
+
+        for c in 0..4 {
+            for r in 0..4 {
+                board.place_piece(c, r, GameCell::Yellow);
+            }
+        }
+        assert!(board.is_full());
+        assert_eq!(board.get(1, 2), GameCell::Red);
+    }
+
+    #[test]
+    fn toroidal_lines_wrap_horizontally() {
+        let mut board = Board::toroidal(7, 6);
+        for &c in &[5, 6, 0] {
+            board.set(c, 0, GameCell::Red);
+            board.set(c, 5, GameCell::Yellow);
+        }
+        assert_eq!(board.lines_through(0, 0, 4), 0);
+        board.set(1, 0, GameCell::Red);
+        assert_ne!(board.lines_through(6, 0, 4), 0);
+        assert_ne!(board.lines_through(1, 0, 4), 0);
+
+        // Diagonals wrap too, but not over the top of the board
+        let mut board = Board::toroidal(7, 6);
+        for (i, &c) in [5, 6, 0, 1].iter().enumerate() {
+            board.set(c, i, GameCell::Yellow);
+        }
+        assert_ne!(board.lines_through(0, 2, 4), 0);
+        let mut board = Board::toroidal(7, 6);
+        for (i, &c) in [5, 6, 0, 1].iter().enumerate() {
+            board.set(c, (i + 4) % 6, GameCell::Yellow);
+        }
+        assert_eq!(board.lines_through(0, 0, 4), 0);
+
+        let mut board = Board::toroidal(7, 6);
+        board.set(5, 2, GameCell::Red);
+        board.set(6, 1, GameCell::Red);
+        assert_ne!(board.winning_cells(GameCell::Red, 3) & board.bit(0, 0), 0);
+    }
+
+    #[test]
+    fn full_board() {
+        let mut board = Board::new(4, 4);
+        for c in 0..4 {
+            for r in 0..4 {
+                assert!(!board.is_full());
+                let cell = if (c / 2 + r) % 2 == 0 { GameCell::Red } else { GameCell::Yellow };
+                assert_eq!(board.drop_piece(c, cell), Some(r));
+            }
+            assert_eq!(board.drop_piece(c, GameCell::Red), None);
+        }
+        assert!(board.is_full());
+    }
+}
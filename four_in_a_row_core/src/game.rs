@@ -0,0 +1,1098 @@
+//! The rules of a game: whose turn it is, which moves are legal and when the game is over.
+
+use crate::board::{Bitmask, Board, GameCell, MAX_BOARD_SIZE, MIN_BOARD_SIZE, PLAYER_COLORS};
+use crate::solver;
+
+/// Number of pieces a player must collect to win a game of Pop 10
+pub const POP_TEN_TARGET: u32 = 10;
+/// Shortest line that may be required to win
+pub const MIN_WIN_LENGTH: usize = 3;
+
+/// The colour played by the player at `index` in the turn order. Teammates sit two apart.
+pub fn player_color(index: usize, teams: bool) -> GameCell {
+    if teams {
+        PLAYER_COLORS[index % 2]
+    } else {
+        PLAYER_COLORS[index]
+    }
+}
+
+/// Index into per-colour arrays, in the order of `PLAYER_COLORS`
+///
+/// # Panic
+/// Panics if `color` is `GameCell::Empty`
+pub fn color_index(color: GameCell) -> usize {
+    PLAYER_COLORS.iter().position(|&c| c == color).expect("not a player colour")
+}
+
+/// What a game is played on and by
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rules {
+    /// Number of columns on the board
+    pub width: usize,
+    /// Number of rows on the board
+    pub height: usize,
+    /// Number of pieces in a row needed to win
+    pub win_length: usize,
+    pub variant: Variant,
+    /// Whether four players form two teams of two, with teammates sharing a colour
+    pub teams: bool,
+    /// The position to start from instead of an empty board
+    pub setup: Option<Board>,
+}
+
+impl Rules {
+    /// Checks that a game of `players` can be played by these rules
+    pub fn check(&self, players: usize) -> Result<(), RulesError> {
+        let sizes = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+        if !sizes.contains(&self.width) || !sizes.contains(&self.height) {
+            return Err(RulesError::BoardSize);
+        }
+        // A line must fit on the board in at least one direction
+        if self.win_length < MIN_WIN_LENGTH || self.win_length > self.width.max(self.height) {
+            return Err(RulesError::WinLength);
+        }
+        // Lines can't wrap all the way around and reuse their own cells
+        if self.variant == Variant::Toroidal && self.win_length > self.width {
+            return Err(RulesError::WrappingLine);
+        }
+        // Both players need enough pieces to be able to collect them
+        if self.variant == Variant::PopTen && self.width * self.height < 2 * POP_TEN_TARGET as usize {
+            return Err(RulesError::PopTenBoard);
+        }
+        // Teams are always two against two
+        if self.teams && players != 4 {
+            return Err(RulesError::Teams);
+        }
+        if !(2..=PLAYER_COLORS.len()).contains(&players) {
+            return Err(RulesError::PlayerCount);
+        }
+        if players > 2 && !self.variant.multiplayer_support() {
+            return Err(RulesError::TwoPlayersOnly(self.variant));
+        }
+        if self.setup.is_some() {
+            if matches!(self.variant, Variant::PopTen | Variant::Anywhere) {
+                return Err(RulesError::VariantSetup(self.variant));
+            }
+            if players > 2 && !self.teams {
+                return Err(RulesError::MultiplayerSetup);
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an empty board for a game with these rules
+    pub fn new_board(&self) -> Board {
+        if self.variant == Variant::Toroidal {
+            Board::toroidal(self.width, self.height)
+        } else {
+            Board::new(self.width, self.height)
+        }
+    }
+
+    /// The board a game with these rules starts on
+    pub fn start_board(&self) -> Board {
+        self.setup.unwrap_or_else(|| self.new_board())
+    }
+
+    /// The colour that moves first. Red starts unless the game is set up from a position where
+    /// red has already made the extra move.
+    pub fn first_color(&self) -> GameCell {
+        match self.setup {
+            Some(board) if board.pieces(GameCell::Red).count_ones() > board.pieces(GameCell::Yellow).count_ones() => {
+                GameCell::Yellow
+            }
+            _ => GameCell::Red,
+        }
+    }
+}
+
+impl Default for Rules {
+    /// Standard Connect 4 on an empty 7x6 board
+    fn default() -> Self {
+        Rules {
+            width: solver::WIDTH,
+            height: solver::HEIGHT,
+            win_length: solver::WIN_LENGTH,
+            variant: Variant::default(),
+            teams: false,
+            setup: None,
+        }
+    }
+}
+
+/// Reasons a game can't be played by a set of rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulesError {
+    /// The board is smaller than `MIN_BOARD_SIZE` or larger than `MAX_BOARD_SIZE`
+    BoardSize,
+    /// The line needed to win is shorter than `MIN_WIN_LENGTH` or doesn't fit on the board
+    WinLength,
+    /// The line needed to win is longer than a toroidal board is wide
+    WrappingLine,
+    /// The board doesn't hold enough pieces for both players to collect `POP_TEN_TARGET`
+    PopTenBoard,
+    /// A team game doesn't have four players
+    Teams,
+    /// There are fewer than two players or more than there are colours
+    PlayerCount,
+    /// The variant can only be played by two players
+    TwoPlayersOnly(Variant),
+    /// The variant can't start from a position
+    VariantSetup(Variant),
+    /// A game between more than two sides starts from a position
+    MultiplayerSetup,
+}
+
+impl std::fmt::Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            RulesError::BoardSize => {
+                write!(f, "boards must be {} to {} cells in each direction", MIN_BOARD_SIZE, MAX_BOARD_SIZE)
+            }
+            RulesError::WinLength => write!(f, "the line needed to win doesn't fit on the board"),
+            RulesError::WrappingLine => write!(f, "on a toroidal board, lines can't be longer than the board is wide"),
+            RulesError::PopTenBoard => {
+                write!(f, "the board is too small for both players to collect {} pieces", POP_TEN_TARGET)
+            }
+            RulesError::Teams => write!(f, "team games need four players"),
+            RulesError::PlayerCount => write!(f, "games need 2 to {} players", PLAYER_COLORS.len()),
+            RulesError::TwoPlayersOnly(variant) => write!(f, "{} games are for two players only", variant),
+            RulesError::VariantSetup(variant) => write!(f, "{} games can't start from a position", variant),
+            RulesError::MultiplayerSetup => write!(f, "only games between two sides can start from a position"),
+        }
+    }
+}
+
+/// A game in progress. Players are identified by `P`, which can be anything the caller likes.
+#[derive(Debug, Clone)]
+pub struct Game<P> {
+    /// Players in turn order. Each plays the colour at the same index of `PLAYER_COLORS`, except
+    /// in team games where teammates sit two apart and share a colour.
+    players: Vec<P>,
+    /// Index into `players` of whoever's turn it is
+    turn: usize,
+    /// Which players have been knocked out of a game of more than two. Their pieces stay on the
+    /// board.
+    eliminated: Vec<bool>,
+    /// Who was knocked out and when, in order, so the game can be replayed
+    eliminations: Vec<Elimination>,
+    board: Board,
+    rules: Rules,
+    /// Moves played so far, in order
+    moves: Vec<Move>,
+    /// The state before each move in `moves`, so that moves can be taken back
+    history: Vec<Snapshot>,
+    phase: Phase,
+    /// Number of pieces each player has collected in Pop 10, indexed by colour
+    collected: [u32; PLAYER_COLORS.len()],
+    /// The cells of the line (or lines) that won the game, or 0 if nobody has made one yet
+    winning_line: Bitmask,
+    /// How the game ended, once it has
+    outcome: Option<MoveOutcome>,
+}
+
+impl<P> Game<P> {
+    /// Creates a game on the board given by `rules`. Players take turns in the order given,
+    /// starting with red. In team games the players must alternate between the teams.
+    ///
+    /// # Panic
+    /// Panics if there are more players than colours
+    pub fn new(rules: Rules, players: Vec<P>) -> Self {
+        assert!(players.len() <= PLAYER_COLORS.len(), "too many players");
+        Game {
+            // Only two-sided games can be set up, so a yellow start is always the second player
+            turn: if rules.first_color() == GameCell::Yellow { 1 } else { 0 },
+            eliminated: vec![false; players.len()],
+            eliminations: Vec::new(),
+            players,
+            board: rules.start_board(),
+            phase: if rules.variant == Variant::PopTen { Phase::Setup } else { Phase::Play },
+            rules,
+            moves: Vec::new(),
+            history: Vec::new(),
+            collected: [0; PLAYER_COLORS.len()],
+            winning_line: 0,
+            outcome: None,
+        }
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Players in turn order
+    pub fn players(&self) -> &[P] {
+        &self.players
+    }
+
+    /// Index into `players` of whoever's turn it is
+    pub fn turn(&self) -> usize {
+        self.turn
+    }
+
+    pub fn current_player(&self) -> &P {
+        &self.players[self.turn]
+    }
+
+    /// The colour of the pieces the current player places
+    pub fn current_color(&self) -> GameCell {
+        self.color_of(self.turn)
+    }
+
+    /// The colour of the pieces placed by the player at `index` in `players`
+    pub fn color_of(&self, index: usize) -> GameCell {
+        player_color(index, self.rules.teams)
+    }
+
+    /// The colours in play, in turn order
+    pub fn colors(&self) -> &[GameCell] {
+        let sides = if self.rules.teams { 2 } else { self.players.len() };
+        &PLAYER_COLORS[..sides]
+    }
+
+    /// Moves played so far, in order
+    pub fn moves(&self) -> &[Move] {
+        &self.moves
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// Number of pieces `color` has collected in a game of Pop 10
+    pub fn collected(&self, color: GameCell) -> u32 {
+        self.collected[color_index(color)]
+    }
+
+    /// The cells of the line (or lines) that won the game, or 0 if nobody has made one
+    pub fn winning_line(&self) -> Bitmask {
+        self.winning_line
+    }
+
+    /// How the game ended: `Win`, `Draw` or `Collected`, or `None` while it is still being played
+    pub fn outcome(&self) -> Option<MoveOutcome> {
+        self.outcome
+    }
+
+    /// Returns true once the game has been won or drawn. No more moves can be played after that.
+    pub fn is_over(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// Returns true if the player at `index` has been knocked out of the game
+    pub fn is_eliminated(&self, index: usize) -> bool {
+        self.eliminated[index]
+    }
+
+    /// Number of players who haven't been eliminated
+    pub fn players_left(&self) -> usize {
+        self.eliminated.iter().filter(|&&e| !e).count()
+    }
+
+    /// Players knocked out so far, in the order it happened
+    pub fn eliminations(&self) -> &[Elimination] {
+        &self.eliminations
+    }
+
+    /// Knocks the current player out of the game and passes the turn on
+    pub fn eliminate_current_player(&mut self) {
+        self.eliminate(self.turn);
+    }
+
+    /// Knocks the player at `index` out of the game, passing the turn on if it was theirs
+    pub fn eliminate(&mut self, index: usize) {
+        self.eliminated[index] = true;
+        self.eliminations.push(Elimination { player: index, after_moves: self.moves.len() });
+        if index == self.turn {
+            self.advance_turn();
+        }
+    }
+
+    /// The player at `index` resigns. With more than two players left they are eliminated and
+    /// the rest play on, otherwise the game is over and the other side wins. Returns
+    /// `MoveOutcome::Illegal` if the game is already over.
+    pub fn resign(&mut self, index: usize) -> MoveOutcome {
+        if self.is_over() {
+            return MoveOutcome::Illegal;
+        }
+        let color = self.color_of(index);
+        if !self.rules.teams && self.players_left() > 2 {
+            self.eliminate(index);
+            return MoveOutcome::Continue;
+        }
+
+        let winner = (0..self.players.len())
+            .find(|&i| !self.eliminated[i] && self.color_of(i) != color)
+            .map(|i| self.color_of(i))
+            .expect("nobody left to win");
+        self.outcome = Some(MoveOutcome::Win(winner));
+        MoveOutcome::Win(winner)
+    }
+
+    /// Saves the parts of the state that a move changes
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            board: self.board,
+            turn: self.turn,
+            phase: self.phase,
+            collected: self.collected,
+        }
+    }
+
+    /// Adds a move to the history, along with the state from before it was played
+    fn push_move(&mut self, mv: Move, before: Snapshot) {
+        self.moves.push(mv);
+        self.history.push(before);
+    }
+
+    /// Returns true if the player at `index` has a move that could be taken back
+    pub fn can_take_back(&self, index: usize) -> bool {
+        !self.is_over() && self.history.iter().any(|before| before.turn == index)
+    }
+
+    /// Takes back the last move made by the player at `index`, along with every move made since,
+    /// so that it is their turn again. Returns false if they haven't moved yet or the game is over.
+    pub fn take_back(&mut self, index: usize) -> bool {
+        if self.is_over() {
+            return false;
+        }
+        let start = match self.history.iter().rposition(|before| before.turn == index) {
+            Some(start) => start,
+            None => return false,
+        };
+        let before = self.history[start];
+        self.history.truncate(start);
+        self.moves.truncate(start);
+        // Players knocked out during the moves taken back stay out
+        for elimination in &mut self.eliminations {
+            elimination.after_moves = elimination.after_moves.min(start);
+        }
+
+        self.board = before.board;
+        self.turn = before.turn;
+        self.phase = before.phase;
+        self.collected = before.collected;
+        true
+    }
+
+    /// Index into `players` of whoever moves after the current player
+    fn next_turn(&self) -> usize {
+        let n = self.players.len();
+        (1..=n)
+            .map(|i| (self.turn + i) % n)
+            .find(|&i| !self.eliminated[i])
+            .unwrap_or(self.turn)
+    }
+
+    fn advance_turn(&mut self) {
+        self.turn = self.next_turn();
+    }
+
+    /// The current player makes a move
+    ///
+    /// Returns `MoveOutcome::Illegal` if the move can't be made: the game is over, the move is off
+    /// the board, the column is full, or it is a pop and the bottom piece isn't the current
+    /// player's (or this isn't a PopOut game)
+    pub fn play_move(&mut self, mv: Move) -> MoveOutcome {
+        if self.is_over() || !self.on_board(mv) {
+            return MoveOutcome::Illegal;
+        }
+
+        let outcome = if self.rules.variant == Variant::PopTen {
+            self.play_pop_ten_move(mv)
+        } else {
+            self.play_regular_move(mv)
+        };
+        if !matches!(outcome, MoveOutcome::Continue | MoveOutcome::Illegal) {
+            self.outcome = Some(outcome);
+        }
+        outcome
+    }
+
+    /// `play_move` for every variant except Pop 10
+    fn play_regular_move(&mut self, mv: Move) -> MoveOutcome {
+        let before = self.snapshot();
+        let color = self.current_color();
+
+        // Pieces are placed by coordinates in gravity-free games, and dropped into columns otherwise
+        let anywhere = self.rules.variant == Variant::Anywhere;
+        let outcome = match mv {
+            Move::Drop(_) | Move::Pop(_) if anywhere => return MoveOutcome::Illegal,
+            Move::Place(..) if !anywhere => return MoveOutcome::Illegal,
+            Move::Place(column, row) => {
+                if !self.board.place_piece(column, row, color) {
+                    return MoveOutcome::Illegal;
+                }
+                self.check_move(row, column)
+            }
+            Move::Drop(column) => match self.board.drop_piece(column, color) {
+                Some(row) => self.check_move(row, column),
+                None => return MoveOutcome::Illegal,
+            },
+            Move::Pop(column) => {
+                if self.rules.variant != Variant::PopOut || self.board.try_get(column, 0) != Some(color) {
+                    return MoveOutcome::Illegal;
+                }
+                self.board.pop_piece(column);
+                self.check_pop()
+            }
+        };
+        self.push_move(mv, before);
+
+        if outcome == MoveOutcome::Continue {
+            self.advance_turn();
+        }
+
+        outcome
+    }
+
+    /// Returns true if the column or cell `mv` is played in exists
+    fn on_board(&self, mv: Move) -> bool {
+        match mv {
+            Move::Drop(column) | Move::Pop(column) => column < self.board.width(),
+            Move::Place(column, row) => column < self.board.width() && row < self.board.height(),
+        }
+    }
+
+    /// Checks if the specified checker is part of a line of `rules.win_length` pieces, which wins
+    /// the game for its owner (or for their opponent, in misère). The line is saved in
+    /// `winning_line`.
+    ///
+    /// # Panic
+    /// Panics if the specified checker is out of bounds or empty
+    fn check_move(&mut self, row: usize, column: usize) -> MoveOutcome {
+        let line = self.board.lines_through(column, row, self.rules.win_length);
+        if line != 0 {
+            let cell = self.board.get(column, row);
+            // In misère, whoever completes a line loses
+            let winner = if self.rules.variant == Variant::Misere { cell.other() } else { cell };
+            assert_ne!(winner, GameCell::Empty);
+            self.winning_line = line;
+            return MoveOutcome::Win(winner);
+        }
+
+        // Finally, check for a draw. In PopOut, the next player may still be able to pop.
+        if self.board.is_full() && !self.can_pop(self.color_of(self.next_turn())) {
+            return MoveOutcome::Draw
+        }
+
+        MoveOutcome::Continue
+    }
+
+    /// Pop 10 version of `play_move`. During setup players fill the board without checking for
+    /// lines, then they take turns popping their own pieces off the bottom row. A popped piece that
+    /// was part of a line is collected, otherwise it goes back on top of its column.
+    fn play_pop_ten_move(&mut self, mv: Move) -> MoveOutcome {
+        let before = self.snapshot();
+        let color = self.current_color();
+
+        match (self.phase, mv) {
+            (Phase::Setup, Move::Drop(column)) => {
+                if self.board.drop_piece(column, color).is_none() {
+                    return MoveOutcome::Illegal;
+                }
+                if self.board.is_full() {
+                    self.phase = Phase::Play;
+                }
+            }
+            (Phase::Play, Move::Pop(column)) => {
+                if self.board.try_get(column, 0) != Some(color) {
+                    return MoveOutcome::Illegal;
+                }
+                let in_line = self.board.lines_through(column, 0, self.rules.win_length) != 0;
+                self.board.pop_piece(column);
+                if in_line {
+                    self.collected[color_index(color)] += 1;
+                    if self.collected[color_index(color)] >= POP_TEN_TARGET {
+                        self.push_move(mv, before);
+                        return MoveOutcome::Collected(color);
+                    }
+                } else {
+                    self.board.drop_piece(column, color);
+                }
+            }
+            _ => return MoveOutcome::Illegal,
+        }
+        self.push_move(mv, before);
+
+        // Players with nothing to pop miss their turn
+        let next = self.next_turn();
+        if self.phase == Phase::Setup || self.can_pop(self.color_of(next)) {
+            self.turn = next;
+        } else if !self.can_pop(color) {
+            return MoveOutcome::Draw;
+        }
+        MoveOutcome::Continue
+    }
+
+    /// Checks for lines after the current player has popped a piece. This can complete lines for
+    /// several players at once, in which case the player who popped wins. The winner's lines are
+    /// saved in `winning_line`.
+    fn check_pop(&mut self) -> MoveOutcome {
+        let win_length = self.rules.win_length;
+        let winner = std::iter::once(self.current_color())
+            .chain(self.colors().iter().copied())
+            .map(|color| (color, self.board.lines_of(color, win_length)))
+            .find(|&(_, lines)| lines != 0);
+        match winner {
+            Some((color, lines)) => {
+                self.winning_line = lines;
+                MoveOutcome::Win(color)
+            }
+            None => MoveOutcome::Continue,
+        }
+    }
+
+    /// Returns true if `color` is allowed to pop any of their pieces
+    fn can_pop(&self, color: GameCell) -> bool {
+        let allowed = match self.rules.variant {
+            Variant::Standard | Variant::Misere | Variant::Anywhere | Variant::Toroidal => false,
+            Variant::PopOut => true,
+            Variant::PopTen => self.phase == Phase::Play,
+        };
+        allowed && (0..self.board.width()).any(|c| self.board.get(c, 0) == color)
+    }
+}
+
+/// Everything a move can change in a `Game`
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    board: Board,
+    turn: usize,
+    phase: Phase,
+    collected: [u32; PLAYER_COLORS.len()],
+}
+
+/// A player being knocked out of a game of more than two
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elimination {
+    /// Index of the player in turn order
+    pub player: usize,
+    /// Number of moves that had been played when it happened
+    pub after_moves: usize,
+}
+
+/// Stages of a game. Only Pop 10 has a separate setup stage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Players take turns filling the board
+    Setup,
+    Play,
+}
+
+/// A single turn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Move {
+    /// Drop a piece into a column
+    Drop(usize),
+    /// Remove the current player's piece from the bottom of a column (PopOut only)
+    Pop(usize),
+    /// Put a piece in any empty cell, given as a column and row (gravity-free games only)
+    Place(usize, usize),
+}
+
+impl std::fmt::Display for Move {
+    /// Writes the move in notation: a drop is the column number counted from 1, a pop is the
+    /// column number prefixed with `p`, and a placed piece is a column letter and a row number
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Move::Drop(column) => write!(f, "{}", column + 1),
+            Move::Pop(column) => write!(f, "p{}", column + 1),
+            Move::Place(column, row) => write!(f, "{}{}", (b'a' + column as u8) as char, row + 1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveOutcome {
+    Win(GameCell),
+    Draw,
+    /// The given player collected enough pieces to win a game of Pop 10
+    Collected(GameCell),
+    Continue,
+    Illegal,
+}
+
+/// The rules a game is played by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Variant {
+    #[default]
+    Standard,
+    /// Players may remove one of their own pieces from the bottom of a column instead of dropping one
+    PopOut,
+    /// Players fill the board, then pop their own pieces that are part of lines to collect them
+    PopTen,
+    /// Completing a line loses instead of winning
+    Misere,
+    /// Pieces can be placed in any empty cell rather than dropped into columns
+    Anywhere,
+    /// Horizontal and diagonal lines wrap around from the last column to the first
+    Toroidal,
+}
+
+impl Variant {
+    /// Every variant
+    pub const ALL: [Variant; 6] = [
+        Variant::Standard,
+        Variant::PopOut,
+        Variant::PopTen,
+        Variant::Misere,
+        Variant::Anywhere,
+        Variant::Toroidal,
+    ];
+
+    /// Returns true if the bot, hints and analysis understand this variant
+    pub fn engine_support(self) -> bool {
+        matches!(self, Variant::Standard | Variant::Toroidal)
+    }
+
+    /// Returns true if games of this variant can have more than two players
+    pub fn multiplayer_support(self) -> bool {
+        matches!(self, Variant::Standard | Variant::Anywhere | Variant::Toroidal)
+    }
+}
+
+impl std::fmt::Display for Variant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Variant::Standard => "Standard",
+            Variant::PopOut => "PopOut",
+            Variant::PopTen => "Pop 10",
+            Variant::Misere => "Misère",
+            Variant::Anywhere => "Connect Anywhere",
+            Variant::Toroidal => "Toroidal",
+        })
+    }
+}
+
+impl std::str::FromStr for Variant {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "standard" => Ok(Variant::Standard),
+            "popout" => Ok(Variant::PopOut),
+            // Also accept the display names, which game records use
+            "pop10" | "pop 10" => Ok(Variant::PopTen),
+            "misere" | "misère" => Ok(Variant::Misere),
+            "anywhere" | "connect anywhere" => Ok(Variant::Anywhere),
+            "toroidal" | "wrap" => Ok(Variant::Toroidal),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOARD_SIZES: &[(usize, usize)] = &[(7, 6), (4, 4), (8, 7), (9, 7), (9, 9)];
+    const WIN_LENGTHS: &[usize] = &[3, 4, 5];
+
+    /// Every board size and win length combination where a line fits in both directions
+    fn configs() -> impl Iterator<Item = (usize, usize, usize)> {
+        BOARD_SIZES.iter().flat_map(|&(width, height)| {
+            WIN_LENGTHS
+                .iter()
+                .filter(move |&&n| n <= width && n <= height)
+                .map(move |&n| (width, height, n))
+        })
+    }
+
+    fn new_game(width: usize, height: usize, win_length: usize) -> Game<()> {
+        let rules = Rules {
+            width,
+            height,
+            win_length,
+            ..Rules::default()
+        };
+        Game::new(rules, vec![(); 2])
+    }
+
+    #[test]
+    fn horizontal_win() {
+        for (width, height, n) in configs() {
+            for color in &PLAYER_COLORS {
+                for y in 0..height {
+                    for x in 0..(width-(n-1)) {
+                        let mut game = new_game(width, height, n);
+                        for c in x..x+n {
+                            game.board.set(c, y, *color);
+                        }
+
+                        for c in x..x+n {
+                            assert_eq!(game.check_move(y, c), MoveOutcome::Win(*color));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn vertical_win() {
+        for (width, height, n) in configs() {
+            for color in &PLAYER_COLORS {
+                for y in 0..(height-(n-1)) {
+                    for x in 0..width {
+                        let mut game = new_game(width, height, n);
+                        for r in y..y+n {
+                            game.board.set(x, r, *color);
+                        }
+
+                        for r in y..y+n {
+                            assert_eq!(game.check_move(r, x), MoveOutcome::Win(*color));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nw_diagonal_win() {
+        for (width, height, n) in configs() {
+            for color in &PLAYER_COLORS {
+                for y in 0..(height-(n-1)) {
+                    for x in 0..(width-(n-1)) {
+                        let mut game = new_game(width, height, n);
+                        for i in 0..n {
+                            game.board.set(x+i, y+i, *color);
+                        }
+
+                        for i in 0..n {
+                            assert_eq!(game.check_move(y+i, x+i), MoveOutcome::Win(*color));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ne_diagonal_win() {
+        for (width, height, n) in configs() {
+            for color in &PLAYER_COLORS {
+                for y in 0..(height-(n-1)) {
+                    for x in 0..(width-(n-1)) {
+                        let mut game = new_game(width, height, n);
+                        for i in 0..n {
+                            game.board.set(x+i, y+(n-1-i), *color);
+                        }
+
+                        for i in 0..n {
+                            assert_eq!(game.check_move(y+(n-1-i), x+i), MoveOutcome::Win(*color));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn full_column_is_illegal() {
+        for &(width, height) in BOARD_SIZES {
+            let mut game = new_game(width, height, solver::WIN_LENGTH);
+            for _ in 0..height {
+                assert_ne!(game.play_move(Move::Drop(width - 1)), MoveOutcome::Illegal);
+            }
+            assert_eq!(game.play_move(Move::Drop(width - 1)), MoveOutcome::Illegal);
+        }
+    }
+
+    #[test]
+    fn moves_off_the_board_are_illegal() {
+        let mut game = new_game(7, 6, 4);
+        assert_eq!(game.play_move(Move::Drop(7)), MoveOutcome::Illegal);
+        assert_eq!(game.play_move(Move::Drop(usize::MAX)), MoveOutcome::Illegal);
+
+        game.rules.variant = Variant::PopOut;
+        assert_eq!(game.play_move(Move::Pop(9)), MoveOutcome::Illegal);
+
+        let rules = Rules { variant: Variant::PopTen, ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 2]);
+        assert_eq!(game.play_move(Move::Drop(7)), MoveOutcome::Illegal);
+        assert!(game.moves().is_empty());
+    }
+
+    #[test]
+    fn pop_completing_both_lines_wins_for_popper() {
+        let mut game = new_game(7, 6, 4);
+        game.rules.variant = Variant::PopOut;
+        for c in 0..3 {
+            game.board.set(c, 0, GameCell::Yellow);
+            game.board.set(c, 1, GameCell::Red);
+        }
+        game.board.set(3, 0, GameCell::Red);
+        game.board.set(3, 1, GameCell::Yellow);
+        game.board.set(3, 2, GameCell::Red);
+
+        // Popping shifts a yellow piece into the bottom row and a red piece into the second
+        assert_eq!(game.play_move(Move::Pop(3)), MoveOutcome::Win(GameCell::Red));
+    }
+
+    #[test]
+    fn only_own_pieces_can_be_popped() {
+        let mut game = new_game(7, 6, 4);
+        game.play_move(Move::Drop(0));
+        assert_eq!(game.play_move(Move::Pop(0)), MoveOutcome::Illegal);
+        game.play_move(Move::Drop(1));
+        // Pops aren't allowed outside of PopOut
+        assert_eq!(game.play_move(Move::Pop(0)), MoveOutcome::Illegal);
+
+        game.rules.variant = Variant::PopOut;
+        assert_eq!(game.play_move(Move::Pop(1)), MoveOutcome::Illegal);
+        assert_eq!(game.play_move(Move::Pop(0)), MoveOutcome::Continue);
+        assert_eq!(game.board.column_height(0), 0);
+    }
+
+    #[test]
+    fn pop_ten_fills_board_before_popping() {
+        let rules = Rules { variant: Variant::PopTen, ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 2]);
+        assert_eq!(game.play_move(Move::Pop(0)), MoveOutcome::Illegal);
+
+        // Lines made while filling the board don't count
+        for c in 0..7 {
+            for _ in 0..6 {
+                assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+            }
+        }
+        assert_eq!(game.phase, Phase::Play);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Illegal);
+    }
+
+    #[test]
+    fn pop_ten_collects_pieces_in_lines() {
+        let mut game = new_game(7, 6, 4);
+        game.rules.variant = Variant::PopTen;
+        game.phase = Phase::Play;
+        for c in 0..4 {
+            game.board.set(c, 0, GameCell::Red);
+        }
+        game.board.set(4, 0, GameCell::Yellow);
+        game.board.set(5, 0, GameCell::Red);
+        game.board.set(5, 1, GameCell::Yellow);
+
+        // A piece that isn't part of a line goes back on top of its column
+        assert_eq!(game.play_move(Move::Pop(5)), MoveOutcome::Continue);
+        assert_eq!(game.board.get(5, 0), GameCell::Yellow);
+        assert_eq!(game.board.get(5, 1), GameCell::Red);
+        assert_eq!(game.collected, [0; 4]);
+
+        assert_eq!(game.play_move(Move::Pop(4)), MoveOutcome::Continue);
+        game.collected[0] = POP_TEN_TARGET - 1;
+        assert_eq!(game.play_move(Move::Pop(0)), MoveOutcome::Collected(GameCell::Red));
+    }
+
+    #[test]
+    fn misere_line_loses() {
+        let mut game = new_game(7, 6, 4);
+        game.rules.variant = Variant::Misere;
+        for c in 0..4 {
+            game.board.set(c, 0, GameCell::Red);
+            game.board.set(c, 1, GameCell::Yellow);
+        }
+        assert_eq!(game.check_move(0, 3), MoveOutcome::Win(GameCell::Yellow));
+        assert_eq!(game.check_move(1, 3), MoveOutcome::Win(GameCell::Red));
+    }
+
+    #[test]
+    fn anywhere_takes_coordinates() {
+        let mut game = new_game(7, 6, 4);
+        game.rules.variant = Variant::Anywhere;
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Illegal);
+        assert_eq!(game.play_move(Move::Place(7, 0)), MoveOutcome::Illegal);
+
+        // Red builds a floating diagonal while yellow plays along the bottom
+        for i in 0..3 {
+            assert_eq!(game.play_move(Move::Place(i + 1, i + 2)), MoveOutcome::Continue);
+            assert_eq!(game.play_move(Move::Place(i, 0)), MoveOutcome::Continue);
+        }
+        assert_eq!(game.play_move(Move::Place(0, 0)), MoveOutcome::Illegal);
+        assert_eq!(game.play_move(Move::Place(4, 5)), MoveOutcome::Win(GameCell::Red));
+    }
+
+    #[test]
+    fn toroidal_line_wraps_around() {
+        let rules = Rules { variant: Variant::Toroidal, ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 2]);
+        for &c in &[5, 6, 0] {
+            assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+            assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+        }
+        assert_eq!(game.play_move(Move::Drop(1)), MoveOutcome::Win(GameCell::Red));
+    }
+
+    #[test]
+    fn winning_line_is_highlighted() {
+        let mut game = new_game(7, 6, 4);
+        for c in 0..3 {
+            assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+            assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+        }
+        assert_eq!(game.winning_line, 0);
+        assert_eq!(game.play_move(Move::Drop(3)), MoveOutcome::Win(GameCell::Red));
+
+        let expected = (0..4).fold(0, |line, c| line | game.board.bit(c, 0));
+        assert_eq!(game.winning_line, expected);
+    }
+
+    #[test]
+    fn finished_games_stay_finished() {
+        let mut game = new_game(7, 6, 4);
+        for c in 0..3 {
+            game.play_move(Move::Drop(c));
+            game.play_move(Move::Drop(c));
+        }
+        assert!(!game.is_over());
+        assert_eq!(game.play_move(Move::Drop(3)), MoveOutcome::Win(GameCell::Red));
+        assert_eq!(game.outcome(), Some(MoveOutcome::Win(GameCell::Red)));
+
+        assert_eq!(game.play_move(Move::Drop(4)), MoveOutcome::Illegal);
+        assert_eq!(game.resign(0), MoveOutcome::Illegal);
+        assert!(!game.take_back(1));
+        assert_eq!(game.moves().len(), 7);
+
+        let mut game = new_game(7, 6, 4);
+        assert_eq!(game.resign(0), MoveOutcome::Win(GameCell::Yellow));
+        assert_eq!(game.outcome(), Some(MoveOutcome::Win(GameCell::Yellow)));
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Illegal);
+    }
+
+    #[test]
+    fn turns_skip_eliminated_players() {
+        let mut game = Game::new(Rules::default(), vec![(); 3]);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Continue);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Continue);
+        assert_eq!(game.current_color(), GameCell::Green);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Continue);
+        assert_eq!(game.current_color(), GameCell::Red);
+
+        game.turn = 1;
+        game.eliminate_current_player();
+        assert_eq!(game.players_left(), 2);
+        assert_eq!(game.current_color(), GameCell::Green);
+        assert_eq!(game.play_move(Move::Drop(1)), MoveOutcome::Continue);
+        assert_eq!(game.current_color(), GameCell::Red);
+        assert_eq!(game.play_move(Move::Drop(1)), MoveOutcome::Continue);
+        assert_eq!(game.current_color(), GameCell::Green);
+    }
+
+    #[test]
+    fn teammates_share_a_colour() {
+        let rules = Rules { teams: true, ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 4]);
+        assert_eq!(game.colors(), &[GameCell::Red, GameCell::Yellow]);
+
+        // The rotation passes through all four players, alternating between the teams
+        for turn in 0..4 {
+            assert_eq!(game.turn, turn);
+            assert_eq!(game.current_color(), PLAYER_COLORS[turn % 2]);
+            assert_eq!(game.play_move(Move::Drop(turn % 2)), MoveOutcome::Continue);
+        }
+        assert_eq!(game.turn, 0);
+
+        // Lines count for the whole team, whoever placed each piece
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Continue);
+        assert_eq!(game.play_move(Move::Drop(1)), MoveOutcome::Continue);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Win(GameCell::Red));
+    }
+
+    #[test]
+    fn resigning_ends_two_sided_games() {
+        let mut game = new_game(7, 6, 4);
+        assert_eq!(game.resign(1), MoveOutcome::Win(GameCell::Red));
+
+        let rules = Rules { teams: true, ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 4]);
+        assert_eq!(game.resign(2), MoveOutcome::Win(GameCell::Yellow));
+    }
+
+    #[test]
+    fn resigning_eliminates_one_of_several_players() {
+        let mut game = Game::new(Rules::default(), vec![(); 3]);
+        assert_eq!(game.resign(0), MoveOutcome::Continue);
+        assert_eq!(game.current_color(), GameCell::Yellow);
+        assert_eq!(game.resign(2), MoveOutcome::Win(GameCell::Yellow));
+    }
+
+    #[test]
+    fn eliminations_are_logged() {
+        let mut game = Game::new(Rules::default(), vec![(); 4]);
+        assert_eq!(game.play_move(Move::Drop(0)), MoveOutcome::Continue);
+        assert_eq!(game.play_move(Move::Drop(1)), MoveOutcome::Continue);
+        game.eliminate(3);
+        assert_eq!(game.current_color(), GameCell::Green);
+        assert_eq!(game.play_move(Move::Drop(2)), MoveOutcome::Continue);
+        game.eliminate_current_player();
+        assert_eq!(game.current_color(), GameCell::Yellow);
+        assert_eq!(game.eliminations(), &[
+            Elimination { player: 3, after_moves: 2 },
+            Elimination { player: 0, after_moves: 3 },
+        ]);
+
+        // Taking back moves doesn't bring anyone back
+        assert!(game.take_back(1));
+        assert_eq!(game.moves().len(), 1);
+        assert_eq!(game.eliminations(), &[
+            Elimination { player: 3, after_moves: 1 },
+            Elimination { player: 0, after_moves: 1 },
+        ]);
+    }
+
+    #[test]
+    fn take_back_restores_earlier_position() {
+        let mut game = new_game(7, 6, 4);
+        assert!(!game.take_back(0));
+        for &c in &[3, 3, 4] {
+            assert_eq!(game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+        }
+        let after_first = game.history[1];
+
+        // Yellow's takeback also undoes red's reply
+        assert!(game.take_back(1));
+        assert_eq!(game.turn, 1);
+        assert_eq!(game.moves, [Move::Drop(3)]);
+        assert_eq!(game.board, after_first.board);
+        assert_eq!(game.board.column_height(3), 1);
+        assert_eq!(game.board.column_height(4), 0);
+
+        assert!(game.take_back(0));
+        assert!(game.moves.is_empty());
+        assert_eq!(game.board.pieces(GameCell::Red), 0);
+        assert!(!game.can_take_back(0));
+    }
+
+    #[test]
+    fn set_up_game_continues_with_side_to_move() {
+        let mut setup = Board::new(7, 6);
+        setup.drop_piece(3, GameCell::Red);
+        setup.drop_piece(3, GameCell::Yellow);
+        setup.drop_piece(4, GameCell::Red);
+        let rules = Rules { setup: Some(setup), ..Rules::default() };
+        let mut game = Game::new(rules, vec![(); 2]);
+        assert_eq!(game.current_color(), GameCell::Yellow);
+        assert_eq!(game.play_move(Move::Drop(3)), MoveOutcome::Continue);
+        assert_eq!(game.board.get(3, 2), GameCell::Yellow);
+        assert_eq!(game.current_color(), GameCell::Red);
+    }
+
+    #[test]
+    fn short_line_does_not_win() {
+        for (width, height, n) in configs() {
+            let mut game = new_game(width, height, n);
+            for c in 0..n-1 {
+                game.board.set(c, 0, GameCell::Red);
+            }
+            for c in 0..n-1 {
+                assert_eq!(game.check_move(0, c), MoveOutcome::Continue);
+            }
+        }
+    }
+}
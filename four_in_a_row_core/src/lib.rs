@@ -0,0 +1,10 @@
+//! The rules of Four in a Row and the engines that play it, without anything specific to Discord.
+//!
+//! `game::Game` keeps track of a game in progress and checks every move against its `Rules`.
+//! Players are whatever the caller uses to identify them, so the same engine can back the bot,
+//! command line tools or any other front-end.
+
+pub mod ai;
+pub mod board;
+pub mod game;
+pub mod solver;
@@ -0,0 +1,655 @@
+//! A perfect-play solver for the standard 7x6 Connect 4 board.
+//!
+//! Positions are scored from the point of view of the player to move: a positive score means they
+//! can force a win, 0 means best play leads to a draw and a negative score means they lose. The
+//! magnitude is the number of cells which will still be empty when the game ends, plus one, halved
+//! (the sooner the win, the bigger the score).
+//!
+//! The search is a negamax with alpha-beta pruning that narrows down the exact score with a series
+//! of null-window searches. Results are cached in a transposition table keyed on positions and
+//! their mirror images, and the first few plies are read from a precomputed opening book.
+
+use crate::board::{Board, GameCell};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+pub const WIDTH: usize = 7;
+pub const HEIGHT: usize = 6;
+pub const WIN_LENGTH: usize = 4;
+
+const CELLS: i32 = (WIDTH * HEIGHT) as i32;
+/// Lowest possible score, losing right after the opponent's fourth piece
+const MIN_SCORE: i32 = -CELLS / 2 + 3;
+/// Highest possible score, winning with our fourth piece
+const MAX_SCORE: i32 = (CELLS + 1) / 2 - 3;
+
+/// Positions with at most this many pieces may be found in the opening book
+pub const BOOK_DEPTH: u32 = 4;
+const OPENING_BOOK: &str = include_str!("../data/opening_book.txt");
+
+/// Number of entries in the transposition table. This is prime, which lets entries store only the
+/// lower 32 bits of their key without collisions.
+const TABLE_SIZE: usize = 4_194_301;
+
+/// How often the search checks whether it has run out of time, in nodes
+const DEADLINE_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// Most solvers kept in `SPARE_SOLVERS`. Each one holds a whole transposition table, so after a
+/// burst of searches at once the extras are dropped rather than kept around for good.
+const MAX_SPARE_SOLVERS: usize = 2;
+
+/// Solvers that aren't in use, kept so that their transposition tables aren't allocated again for
+/// every search
+static SPARE_SOLVERS: Mutex<Vec<Solver>> = Mutex::new(Vec::new());
+
+/// Returns true if the solver can be used on games with this board and win length
+pub fn supports(board: &Board, win_length: usize) -> bool {
+    board.width() == WIDTH && board.height() == HEIGHT && win_length == WIN_LENGTH && !board.wraps()
+}
+
+/// A 7x6 position, laid out the same way as `Board` (7 bits per column)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Position {
+    /// Pieces belonging to the player to move
+    current: u64,
+    /// Every occupied cell
+    mask: u64,
+    moves: u32,
+}
+
+impl Position {
+    /// Converts a board into a position, or returns `None` if the board isn't 7x6
+    pub fn from_board(board: &Board, to_move: GameCell) -> Option<Self> {
+        if board.width() != WIDTH || board.height() != HEIGHT {
+            return None;
+        }
+
+        let current = board.pieces(to_move) as u64;
+        let mask = (board.pieces(GameCell::Red) | board.pieces(GameCell::Yellow)) as u64;
+        Some(Position {
+            current,
+            mask,
+            moves: mask.count_ones(),
+        })
+    }
+
+    /// Replays a sequence of columns numbered from 1, e.g. `"4453"`, or returns `None` if one of
+    /// the moves is illegal or wins the game
+    pub fn from_moves(moves: &str) -> Option<Self> {
+        let mut position = Position::default();
+        for c in moves.chars() {
+            let column = (c.to_digit(10)? as usize).checked_sub(1)?;
+            if column >= WIDTH || !position.can_play(column) || position.is_winning_move(column) {
+                return None;
+            }
+            position.play_column(column);
+        }
+        Some(position)
+    }
+
+    /// Converts a score for this position into the number of moves the winning player still has
+    /// to make, counting the winning move itself. Returns `None` for a draw.
+    pub fn moves_to_win(&self, score: i32) -> Option<u32> {
+        if score == 0 {
+            return None;
+        }
+
+        // A winner with score `s` places their last piece as their `(CELLS + 1) / 2 - s + 1`th
+        let winner_moves_made = if score > 0 {
+            self.moves / 2
+        } else {
+            self.moves.div_ceil(2)
+        };
+        Some(((CELLS + 1) / 2 - score.abs() + 1) as u32 - winner_moves_made)
+    }
+
+    pub fn can_play(&self, column: usize) -> bool {
+        self.mask & top_mask(column) == 0
+    }
+
+    /// # Panic
+    /// May produce an invalid position if the column is full
+    pub fn play_column(&mut self, column: usize) {
+        self.play((self.mask + bottom_mask(column)) & column_mask(column));
+    }
+
+    /// Returns true if the player to move wins by playing in `column`
+    pub fn is_winning_move(&self, column: usize) -> bool {
+        self.winning_position() & self.possible() & column_mask(column) != 0
+    }
+
+    fn play(&mut self, move_bit: u64) {
+        self.current ^= self.mask;
+        self.mask |= move_bit;
+        self.moves += 1;
+    }
+
+    fn can_win_next(&self) -> bool {
+        self.winning_position() & self.possible() != 0
+    }
+
+    /// Returns the playable moves that don't hand the opponent an immediate win
+    fn possible_non_losing_moves(&self) -> u64 {
+        let mut possible = self.possible();
+        let opponent_win = self.opponent_winning_position();
+        let forced = possible & opponent_win;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                // The opponent has two threats, we can't block both
+                return 0;
+            }
+            possible = forced;
+        }
+        // Don't play directly below an opponent's threat
+        possible & !(opponent_win >> 1)
+    }
+
+    /// Rates a move by the number of threats it creates
+    fn move_score(&self, move_bit: u64) -> u32 {
+        winning_cells(self.current | move_bit, self.mask).count_ones()
+    }
+
+    /// Returns a key which is unique to this position and its mirror image
+    fn key(&self) -> u64 {
+        let key = self.current + self.mask;
+        key.min(mirror(key))
+    }
+
+    fn winning_position(&self) -> u64 {
+        winning_cells(self.current, self.mask)
+    }
+
+    fn opponent_winning_position(&self) -> u64 {
+        winning_cells(self.current ^ self.mask, self.mask)
+    }
+
+    fn possible(&self) -> u64 {
+        (self.mask + BOTTOM_MASK) & BOARD_MASK
+    }
+}
+
+/// Bottom cell of every column
+const BOTTOM_MASK: u64 = {
+    let mut mask = 0;
+    let mut column = 0;
+    while column < WIDTH {
+        mask |= 1 << (column * (HEIGHT + 1));
+        column += 1;
+    }
+    mask
+};
+/// Every playable cell
+const BOARD_MASK: u64 = BOTTOM_MASK * ((1 << HEIGHT) - 1);
+
+fn top_mask(column: usize) -> u64 {
+    (1 << (HEIGHT - 1)) << (column * (HEIGHT + 1))
+}
+
+fn bottom_mask(column: usize) -> u64 {
+    1 << (column * (HEIGHT + 1))
+}
+
+fn column_mask(column: usize) -> u64 {
+    ((1 << HEIGHT) - 1) << (column * (HEIGHT + 1))
+}
+
+/// Reverses the order of the columns
+fn mirror(key: u64) -> u64 {
+    let column_bits = (1 << (HEIGHT + 1)) - 1;
+    (0..WIDTH).fold(0, |mirrored, c| {
+        let column = (key >> (c * (HEIGHT + 1))) & column_bits;
+        mirrored | column << ((WIDTH - 1 - c) * (HEIGHT + 1))
+    })
+}
+
+/// Returns the empty cells where `position` would complete a line of four
+fn winning_cells(position: u64, mask: u64) -> u64 {
+    // Vertical
+    let mut r = (position << 1) & (position << 2) & (position << 3);
+
+    // Horizontal and both diagonals
+    for &shift in &[HEIGHT + 1, HEIGHT, HEIGHT + 2] {
+        let p = (position << shift) & (position << (2 * shift));
+        r |= p & (position << (3 * shift));
+        r |= p & (position >> shift);
+        let p = (position >> shift) & (position >> (2 * shift));
+        r |= p & (position << shift);
+        r |= p & (position >> (3 * shift));
+    }
+
+    r & (BOARD_MASK ^ mask)
+}
+
+/// Columns in the order they should be searched, from the centre outwards
+const COLUMN_ORDER: [usize; WIDTH] = [3, 2, 4, 1, 5, 0, 6];
+
+/// Caches bounds on the scores of positions that have already been searched
+struct TranspositionTable {
+    keys: Vec<u32>,
+    values: Vec<u8>,
+}
+
+impl TranspositionTable {
+    /// `size` must be a prime larger than 2^17 for partial keys to be unique
+    fn new(size: usize) -> Self {
+        TranspositionTable {
+            keys: vec![0; size],
+            values: vec![0; size],
+        }
+    }
+
+    fn index(&self, key: u64) -> usize {
+        (key % self.keys.len() as u64) as usize
+    }
+
+    fn put(&mut self, key: u64, value: u8) {
+        let i = self.index(key);
+        self.keys[i] = key as u32;
+        self.values[i] = value;
+    }
+
+    /// Returns 0 if the key isn't in the table
+    fn get(&self, key: u64) -> u8 {
+        let i = self.index(key);
+        if self.keys[i] == key as u32 {
+            self.values[i]
+        } else {
+            0
+        }
+    }
+
+    fn clear(&mut self) {
+        self.keys.iter_mut().for_each(|k| *k = 0);
+        self.values.iter_mut().for_each(|v| *v = 0);
+    }
+}
+
+pub struct Solver {
+    table: TranspositionTable,
+    book: Cow<'static, HashMap<u64, i8>>,
+    /// When to give up searching, if ever
+    deadline: Option<Instant>,
+    timed_out: bool,
+    nodes: u64,
+}
+
+impl Solver {
+    pub fn new() -> Self {
+        Self::with_book(Cow::Borrowed(opening_book()), TABLE_SIZE)
+    }
+
+    fn with_book(book: Cow<'static, HashMap<u64, i8>>, table_size: usize) -> Self {
+        Solver {
+            table: TranspositionTable::new(table_size),
+            book,
+            deadline: None,
+            timed_out: false,
+            nodes: 0,
+        }
+    }
+
+    /// Gives up on any search still running at `deadline`
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Returns the exact score of a position in which nobody has won yet, or `None` if the
+    /// deadline passed first
+    pub fn solve(&mut self, position: &Position) -> Option<i32> {
+        self.timed_out = false;
+
+        if position.can_win_next() {
+            return Some((CELLS + 1 - position.moves as i32) / 2);
+        }
+
+        let mut min = -(CELLS - position.moves as i32) / 2;
+        let mut max = (CELLS + 1 - position.moves as i32) / 2;
+
+        // Narrow down the score with null-window searches, trying small absolute values first
+        // since those are the cheapest to prove
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+
+            let r = self.negamax(position, med, med + 1);
+            if self.timed_out {
+                // Anything stored during an interrupted search can't be trusted
+                self.table.clear();
+                return None;
+            }
+
+            if r <= med {
+                max = r;
+            } else {
+                min = r;
+            }
+        }
+
+        Some(min)
+    }
+
+    /// Returns the score of playing each column, or `None` for full columns. Returns `None` if the
+    /// deadline passed first.
+    pub fn analyze(&mut self, position: &Position) -> Option<[Option<i32>; WIDTH]> {
+        let mut scores = [None; WIDTH];
+        for (column, score) in scores.iter_mut().enumerate() {
+            if !position.can_play(column) {
+                continue;
+            }
+
+            *score = Some(if position.is_winning_move(column) {
+                (CELLS + 1 - position.moves as i32) / 2
+            } else {
+                let mut next = *position;
+                next.play_column(column);
+                -self.solve(&next)?
+            });
+        }
+        Some(scores)
+    }
+
+    /// Returns the best column to play and its score, preferring central columns between equally
+    /// good moves. Returns `None` if the board is full or the deadline passed first.
+    pub fn best_move(&mut self, position: &Position) -> Option<(usize, i32)> {
+        let scores = self.analyze(position)?;
+        COLUMN_ORDER
+            .iter()
+            .filter_map(|&c| scores[c].map(|score| (c, score)))
+            .fold(None, |best: Option<(usize, i32)>, (c, score)| match best {
+                Some((_, best_score)) if best_score >= score => best,
+                _ => Some((c, score)),
+            })
+    }
+
+    fn negamax(&mut self, position: &Position, mut alpha: i32, mut beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(DEADLINE_CHECK_INTERVAL) {
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    self.timed_out = true;
+                }
+            }
+        }
+        if self.timed_out {
+            return alpha;
+        }
+
+        let next = position.possible_non_losing_moves();
+        if next == 0 {
+            // Every move lets the opponent win straight away
+            return -(CELLS - position.moves as i32) / 2;
+        }
+        if position.moves as i32 >= CELLS - 2 {
+            // Neither player can win with the last two pieces
+            return 0;
+        }
+
+        // We can't win before our next move
+        let min = -(CELLS - 2 - position.moves as i32) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+        // Nor on it, since we checked for that before getting here
+        let max = (CELLS - 1 - position.moves as i32) / 2;
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        let key = position.key();
+        let value = self.table.get(key) as i32;
+        if value != 0 {
+            if value > MAX_SCORE - MIN_SCORE + 1 {
+                // Lower bound
+                let min = value + 2 * MIN_SCORE - MAX_SCORE - 2;
+                if alpha < min {
+                    alpha = min;
+                    if alpha >= beta {
+                        return alpha;
+                    }
+                }
+            } else {
+                // Upper bound
+                let max = value + MIN_SCORE - 1;
+                if beta > max {
+                    beta = max;
+                    if alpha >= beta {
+                        return beta;
+                    }
+                }
+            }
+        }
+
+        if position.moves <= BOOK_DEPTH {
+            if let Some(&score) = self.book.get(&key) {
+                return score as i32;
+            }
+        }
+
+        // Search the moves creating the most threats first, then central columns first
+        let mut moves = COLUMN_ORDER
+            .iter()
+            .rev()
+            .map(|&c| next & column_mask(c))
+            .filter(|&m| m != 0)
+            .map(|m| (m, position.move_score(m)))
+            .collect::<Vec<_>>();
+        // Stable, so ties keep the central columns first after reversing
+        moves.sort_by_key(|&(_, score)| score);
+
+        for &(m, _) in moves.iter().rev() {
+            let mut next = *position;
+            next.play(m);
+            let score = -self.negamax(&next, -beta, -alpha);
+
+            if score >= beta {
+                self.table.put(key, (score + MAX_SCORE - 2 * MIN_SCORE + 2) as u8);
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        self.table.put(key, (alpha - MIN_SCORE + 1) as u8);
+        alpha
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Runs `f` with a solver that nothing else is using, reusing a spare one if there is one. Its
+/// transposition table may still hold bounds from earlier searches, which stay valid for any
+/// position.
+pub fn with_solver<T>(f: impl FnOnce(&mut Solver) -> T) -> T {
+    let mut solver = SPARE_SOLVERS.lock().unwrap().pop().unwrap_or_default();
+    solver.set_deadline(None);
+    let result = f(&mut solver);
+    let mut spares = SPARE_SOLVERS.lock().unwrap();
+    if spares.len() < MAX_SPARE_SOLVERS {
+        spares.push(solver);
+    }
+    result
+}
+
+/// The opening book shipped with the solver, parsed the first time it is needed
+fn opening_book() -> &'static HashMap<u64, i8> {
+    static BOOK: OnceLock<HashMap<u64, i8>> = OnceLock::new();
+    BOOK.get_or_init(|| parse_book(OPENING_BOOK))
+}
+
+/// Reads an opening book made of lines containing a move sequence and its score, e.g. `4453 -2`
+fn parse_book(book: &str) -> HashMap<u64, i8> {
+    book.lines()
+        .filter_map(|line| {
+            // The empty board has no moves, so its line starts with the space
+            let (moves, score) = line.rsplit_once(' ')?;
+            Some((Position::from_moves(moves)?.key(), score.parse().ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::collections::HashSet;
+
+    #[test]
+    fn spare_solvers_are_capped() {
+        // Other tests may be using solvers too, so only the cap itself can be relied on
+        with_solver(|_| with_solver(|_| with_solver(|_| ())));
+        assert!(SPARE_SOLVERS.lock().unwrap().len() <= MAX_SPARE_SOLVERS);
+    }
+
+    /// Transposition table size used when generating the opening book, which runs offline and can
+    /// afford to use more memory
+    const GENERATOR_TABLE_SIZE: usize = 67_108_859;
+
+    /// Scores a position by trying every possible continuation
+    fn exhaustive_score(position: &Position) -> i32 {
+        if position.can_win_next() {
+            return (CELLS + 1 - position.moves as i32) / 2;
+        }
+        (0..WIDTH)
+            .filter(|&c| position.can_play(c))
+            .map(|c| {
+                let mut next = *position;
+                next.play_column(c);
+                -exhaustive_score(&next)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn agrees_with_exhaustive_search() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let mut solver = Solver::new();
+
+        let mut checked = 0;
+        while checked < 10 {
+            // Play randomly until there are few enough empty cells to search them all
+            let mut position = Position::default();
+            while position.moves < 35 && !position.can_win_next() {
+                let columns = (0..WIDTH).filter(|&c| position.can_play(c)).collect::<Vec<_>>();
+                position.play_column(columns[rng.gen_range(0, columns.len())]);
+            }
+            if position.can_win_next() {
+                continue;
+            }
+
+            let score = exhaustive_score(&position);
+            assert_eq!(solver.solve(&position), Some(score));
+            assert_eq!(solver.best_move(&position).map(|(_, best)| best), Some(score));
+            checked += 1;
+        }
+    }
+
+    #[test]
+    fn counts_moves_to_win() {
+        let position = Position::from_moves("121212").unwrap();
+        assert_eq!(position.moves_to_win(18), Some(1));
+        let position = Position::from_moves("12121").unwrap();
+        assert_eq!(position.moves_to_win(-18), Some(1));
+        assert_eq!(position.moves_to_win(0), None);
+    }
+
+    #[test]
+    fn mirrored_positions_have_the_same_key() {
+        let position = Position::from_moves("112233").unwrap();
+        let mirrored = Position::from_moves("776655").unwrap();
+        assert_eq!(position.key(), mirrored.key());
+    }
+
+    #[test]
+    fn opening_book_is_consistent() {
+        let book = opening_book();
+
+        // Every position with up to `BOOK_DEPTH` pieces must have an entry, and each entry must
+        // agree with the entries for the positions after it
+        let mut layer = vec![Position::default()];
+        for _ in 0..=BOOK_DEPTH {
+            let mut next_layer = vec![];
+            for position in &layer {
+                let score = *book
+                    .get(&position.key())
+                    .unwrap_or_else(|| panic!("no entry for {:?}", position)) as i32;
+                if position.moves == BOOK_DEPTH {
+                    continue;
+                }
+
+                let mut best = None;
+                for c in (0..WIDTH).filter(|&c| position.can_play(c)) {
+                    let child_score = if position.is_winning_move(c) {
+                        (CELLS + 1 - position.moves as i32) / 2
+                    } else {
+                        let mut next = *position;
+                        next.play_column(c);
+                        next_layer.push(next);
+                        -book.get(&next.key()).copied().unwrap_or_else(|| panic!("no entry for {:?}", next)) as i32
+                    };
+                    best = best.max(Some(child_score));
+                }
+                assert_eq!(best, Some(score), "{:?}", position);
+            }
+            layer = next_layer;
+        }
+    }
+
+    /// Regenerates `data/opening_book.txt`. This takes several hours:
+    /// `cargo test --release -p four_in_a_row_core -- --ignored generate_opening_book`
+    #[test]
+    #[ignore]
+    fn generate_opening_book() {
+        // Every position with up to `BOOK_DEPTH` pieces, leaving out mirror images
+        let mut layers = vec![vec![(Position::default(), String::new())]];
+        for _ in 0..BOOK_DEPTH {
+            let mut seen = HashSet::new();
+            let mut next_layer = vec![];
+            for (position, moves) in layers.last().unwrap() {
+                for c in (0..WIDTH).filter(|&c| position.can_play(c) && !position.is_winning_move(c)) {
+                    let mut next = *position;
+                    next.play_column(c);
+                    if seen.insert(next.key()) {
+                        next_layer.push((next, format!("{}{}", moves, c + 1)));
+                    }
+                }
+            }
+            layers.push(next_layer);
+        }
+
+        // Solve the deepest positions first, so that shallower ones can look them up
+        let mut solver = Solver::with_book(Cow::Owned(HashMap::new()), GENERATOR_TABLE_SIZE);
+        let mut entries = vec![];
+        for layer in layers.iter().rev() {
+            for (position, moves) in layer {
+                let score = solver.solve(position).unwrap();
+                solver.book.to_mut().insert(position.key(), score as i8);
+                entries.push((moves, score));
+            }
+        }
+
+        entries.sort_by_key(|&(moves, _)| (moves.len(), moves));
+        let book = entries
+            .iter()
+            .map(|(moves, score)| format!("{} {}\n", moves, score))
+            .collect::<String>();
+        std::fs::write("data/opening_book.txt", book).unwrap();
+    }
+}
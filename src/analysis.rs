@@ -0,0 +1,301 @@
+//! Post-game analysis, which replays a finished game through the engine and grades every move.
+
+use four_in_a_row_core::ai;
+use four_in_a_row_core::board::{Board, GameCell};
+use four_in_a_row_core::solver::{self, Position, Solver};
+use std::time::{Duration, Instant};
+
+/// How long the solver may spend on each position before falling back to a heuristic search
+const SOLVER_TIME_PER_MOVE: Duration = Duration::from_secs(2);
+/// How long the heuristic search may spend on each position
+const SEARCH_TIME_PER_MOVE: Duration = Duration::from_millis(500);
+/// Heuristic scores this much worse than the best move are mistakes rather than inaccuracies
+const MISTAKE_THRESHOLD: i32 = 4;
+/// Discord rejects messages longer than 2000 characters, so leave room for the header
+const MAX_REPORT_LENGTH: usize = 1800;
+
+/// How good a move is for the player who made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Value {
+    /// The opponent can force a win. Higher values hold out for longer.
+    Loss(i32),
+    /// Nobody can force a win, or the search couldn't see far enough to tell
+    Even(i32),
+    /// The player can force a win. Higher values win sooner.
+    Win(i32),
+}
+
+impl Value {
+    fn from_solver(score: i32) -> Self {
+        match score {
+            s if s > 0 => Value::Win(s),
+            s if s < 0 => Value::Loss(s),
+            _ => Value::Even(0),
+        }
+    }
+
+    fn from_search(score: i32) -> Self {
+        match score {
+            s if s > ai::WIN_SCORE / 2 => Value::Win(s),
+            s if s < -ai::WIN_SCORE / 2 => Value::Loss(s),
+            s => Value::Even(s),
+        }
+    }
+
+    /// The result of the game with best play after `color` makes a move of this value, if known
+    fn outcome(self, color: GameCell, exact: bool) -> Option<Outcome> {
+        match self {
+            Value::Win(_) => Some(Outcome::Win(color)),
+            Value::Loss(_) => Some(Outcome::Win(color.other())),
+            Value::Even(_) if exact => Some(Outcome::Draw),
+            Value::Even(_) => None,
+        }
+    }
+}
+
+/// How a move compares to the best move in the same position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grade {
+    Best,
+    /// Slightly worse than the best move, without changing the result
+    Inaccuracy,
+    /// Misses a win, or gives away a lot of ground
+    Mistake,
+    /// Turns a position that wasn't lost into a loss
+    Blunder,
+}
+
+impl Grade {
+    fn new(best: Value, played: Value) -> Self {
+        match (best, played) {
+            _ if played == best => Grade::Best,
+            (Value::Loss(_), _) => Grade::Inaccuracy,
+            (_, Value::Loss(_)) => Grade::Blunder,
+            (Value::Win(_), Value::Win(_)) => Grade::Inaccuracy,
+            (Value::Win(_), _) => Grade::Mistake,
+            (Value::Even(b), Value::Even(p)) if b - p >= MISTAKE_THRESHOLD => Grade::Mistake,
+            _ => Grade::Inaccuracy,
+        }
+    }
+}
+
+impl std::fmt::Display for Grade {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            Grade::Best => "Best",
+            Grade::Inaccuracy => "Inaccuracy",
+            Grade::Mistake => "Mistake",
+            Grade::Blunder => "Blunder",
+        })
+    }
+}
+
+/// The result of a position if both players play perfectly from there
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Win(GameCell),
+    Draw,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Win(color) => write!(f, "{} wins", color),
+            Outcome::Draw => write!(f, "draw"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveReport {
+    pub column: usize,
+    pub color: GameCell,
+    pub grade: Grade,
+    /// The strongest column in the position, which is `column` if the move was best
+    pub best_column: usize,
+    /// The result with best play before this move, if known
+    pub outcome_before: Option<Outcome>,
+    /// The result with best play after this move, if known
+    pub outcome_after: Option<Outcome>,
+}
+
+impl MoveReport {
+    /// Returns true if this move changed the result of the game with best play
+    pub fn flips_result(&self) -> bool {
+        self.outcome_before.is_some() && self.outcome_after.is_some() && self.outcome_before != self.outcome_after
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Analysis {
+    pub moves: Vec<MoveReport>,
+    /// Whether every position was solved perfectly rather than estimated by a heuristic search
+    pub exact: bool,
+}
+
+/// Replays `moves` (columns, with `first` moving first) starting from `board` and grades each one
+///
+/// This blocks for up to a few seconds per move.
+///
+/// # Panic
+/// Panics if one of the moves is illegal
+pub fn analyze(mut board: Board, first: GameCell, win_length: usize, moves: &[usize]) -> Analysis {
+    let mut positions = Vec::with_capacity(moves.len());
+    let mut color = first;
+    for &column in moves {
+        positions.push((board, color));
+        board.drop_piece(column, color).expect("illegal move in game record");
+        color = color.other();
+    }
+
+    // Analyse from the end of the game backwards, so the solver's transposition table is already
+    // warmed up by the time it reaches the harder early positions
+    solver::with_solver(|solver| analyze_positions(solver, &positions, win_length, moves))
+}
+
+/// Grades each of `moves`, played from the matching position in `positions`
+fn analyze_positions(solver: &mut Solver, positions: &[(Board, GameCell)], win_length: usize, moves: &[usize]) -> Analysis {
+    let mut exact = true;
+    let mut reports = Vec::with_capacity(moves.len());
+    for (&(board, color), &column) in positions.iter().zip(moves).rev() {
+        let (scores, solved) = score_position(solver, &board, color, win_length);
+        exact &= solved;
+
+        let played = scores
+            .iter()
+            .find(|&&(c, _)| c == column)
+            .map(|&(_, value)| value)
+            .unwrap();
+        let (best_column, best) = scores
+            .iter()
+            .copied()
+            .fold(None, |best: Option<(usize, Value)>, (c, value)| match best {
+                // Keep the played move when it ties for best
+                Some((b, best_value)) if best_value > value || (best_value == value && b == column) => best,
+                _ => Some((c, value)),
+            })
+            .unwrap();
+
+        reports.push(MoveReport {
+            column,
+            color,
+            grade: Grade::new(best, played),
+            best_column,
+            outcome_before: best.outcome(color, solved),
+            outcome_after: played.outcome(color, solved),
+        });
+    }
+    reports.reverse();
+
+    Analysis { moves: reports, exact }
+}
+
+/// Values every legal move in a position, along with whether they are exact
+fn score_position(solver: &mut Solver, board: &Board, color: GameCell, win_length: usize) -> (Vec<(usize, Value)>, bool) {
+    if solver::supports(board, win_length) {
+        solver.set_deadline(Some(Instant::now() + SOLVER_TIME_PER_MOVE));
+        let scores = Position::from_board(board, color).and_then(|position| solver.analyze(&position));
+        if let Some(scores) = scores {
+            let scores = scores
+                .iter()
+                .enumerate()
+                .filter_map(|(c, score)| score.map(|s| (c, Value::from_solver(s))))
+                .collect();
+            return (scores, true);
+        }
+    }
+
+    let scores = ai::score_moves(board, color, win_length, SEARCH_TIME_PER_MOVE)
+        .into_iter()
+        .map(|(c, score)| (c, Value::from_search(score)))
+        .collect();
+    (scores, false)
+}
+
+impl Analysis {
+    /// Writes out a summary for each player followed by every move that wasn't best or that
+    /// changed the result. `red` and `yellow` are how the players should be named.
+    pub fn render(&self, red: &str, yellow: &str) -> String {
+        let mut s = String::new();
+        for &(color, name) in &[(GameCell::Red, red), (GameCell::Yellow, yellow)] {
+            let count = |grade| {
+                self.moves
+                    .iter()
+                    .filter(|m| m.color == color && m.grade == grade)
+                    .count()
+            };
+            s.push_str(&format!(
+                "{} {}: best {}, inaccuracies {}, mistakes {}, blunders {}\n",
+                color,
+                name,
+                count(Grade::Best),
+                count(Grade::Inaccuracy),
+                count(Grade::Mistake),
+                count(Grade::Blunder),
+            ));
+        }
+        s.push('\n');
+
+        let notable = self
+            .moves
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.grade != Grade::Best || m.flips_result())
+            .collect::<Vec<_>>();
+        if notable.is_empty() {
+            s.push_str("Every move was the best available.\n");
+        }
+
+        for (i, &(number, m)) in notable.iter().enumerate() {
+            let mut line = format!("`{:>2}.` {} column {}: **{}**", number + 1, m.color, m.column + 1, m.grade);
+            if m.grade != Grade::Best {
+                line.push_str(&format!(" (best was column {})", m.best_column + 1));
+            }
+            if let (true, Some(before), Some(after)) = (m.flips_result(), m.outcome_before, m.outcome_after) {
+                line.push_str(&format!(". Result: {} → {}", before, after));
+            }
+            line.push('\n');
+
+            if s.len() + line.len() > MAX_REPORT_LENGTH {
+                s.push_str(&format!("*…and {} more*\n", notable.len() - i));
+                break;
+            }
+            s.push_str(&line);
+        }
+
+        if !self.exact {
+            s.push_str("\n*Some positions were too complex to solve, so they were graded by a heuristic search.*");
+        }
+        s
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missed_block_is_a_blunder() {
+        // Red stacks column 1 while yellow fails to block it on their third move
+        let moves = [0, 5, 0, 5, 0, 4, 0];
+        let analysis = analyze(Board::new(6, 6), GameCell::Red, 4, &moves);
+        let blunder = &analysis.moves[5];
+
+        assert_eq!(blunder.grade, Grade::Blunder);
+        assert_eq!(blunder.best_column, 0);
+        assert_eq!(blunder.outcome_after, Some(Outcome::Win(GameCell::Red)));
+        assert_eq!(analysis.moves[6].grade, Grade::Best);
+    }
+
+    #[test]
+    fn grades_compare_against_best_move() {
+        assert_eq!(Grade::new(Value::Win(5), Value::Win(5)), Grade::Best);
+        assert_eq!(Grade::new(Value::Win(5), Value::Win(3)), Grade::Inaccuracy);
+        assert_eq!(Grade::new(Value::Win(5), Value::Even(0)), Grade::Mistake);
+        assert_eq!(Grade::new(Value::Win(5), Value::Loss(-3)), Grade::Blunder);
+        assert_eq!(Grade::new(Value::Even(0), Value::Loss(-3)), Grade::Blunder);
+        assert_eq!(Grade::new(Value::Loss(-2), Value::Loss(-8)), Grade::Inaccuracy);
+        assert_eq!(Grade::new(Value::Even(3), Value::Even(2)), Grade::Inaccuracy);
+        assert_eq!(Grade::new(Value::Even(3), Value::Even(-3)), Grade::Mistake);
+    }
+}
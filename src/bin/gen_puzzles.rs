@@ -0,0 +1,112 @@
+//! Generates daily puzzles by having the computer play itself on the standard board, keeping
+//! positions where the player to move has exactly one move that forces a win in 3 to 15 plies.
+//!
+//! Usage: `gen_puzzles [count] [file]`. Finds `count` puzzles (20 by default) and appends them to
+//! `file` (`data/puzzles.txt` by default), skipping positions the file already has. The bot reads
+//! the file when it is built.
+
+use four_in_a_row::puzzle::{self, Puzzle};
+use four_in_a_row_core::ai::{self, Difficulty};
+use four_in_a_row_core::board::GameCell;
+use four_in_a_row_core::game::{Game, Move, MoveOutcome, Rules};
+use four_in_a_row_core::solver::{self, Position, Solver};
+use rand::{seq::SliceRandom, Rng};
+use std::{collections::HashSet, fs::OpenOptions, io::Write, time::{Duration, Instant}};
+
+const DEFAULT_COUNT: usize = 20;
+const DEFAULT_FILE: &str = "data/puzzles.txt";
+/// Shortest and longest forced wins to keep, in plies including the defender's replies
+const MIN_PLIES: u32 = 3;
+const MAX_PLIES: u32 = 15;
+/// Positions this early are mostly opening theory rather than puzzles
+const MIN_MOVES: usize = 8;
+/// How long the solver may spend on each position before it is skipped
+const SOLVE_TIME: Duration = Duration::from_secs(5);
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let count = match args.next().map(|count| count.parse()) {
+        Some(Ok(count)) => count,
+        Some(Err(_)) => {
+            eprintln!("Usage: gen_puzzles [count] [file]");
+            std::process::exit(2);
+        }
+        None => DEFAULT_COUNT,
+    };
+    let path = args.next().unwrap_or_else(|| String::from(DEFAULT_FILE));
+
+    let mut seen = std::fs::read_to_string(&path)
+        .map(|text| text.lines().filter_map(Puzzle::parse).map(|puzzle| puzzle.moves).collect())
+        .unwrap_or_else(|_| HashSet::new());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .unwrap_or_else(|e| panic!("Couldn't open {}: {}", path, e));
+
+    let mut solver = Solver::new();
+    let mut found = 0;
+    while found < count {
+        let puzzle = match find_puzzle(&mut solver) {
+            Some(puzzle) => puzzle,
+            None => continue,
+        };
+        if !seen.insert(puzzle.moves.clone()) {
+            continue;
+        }
+
+        writeln!(file, "{}", puzzle).expect("Couldn't write puzzle");
+        found += 1;
+        println!("{}/{}: {}", found, count, puzzle);
+    }
+}
+
+/// Plays part of a game between randomly chosen computer players, returning the position it
+/// stops at if that makes a puzzle
+fn find_puzzle(solver: &mut Solver) -> Option<Puzzle> {
+    let mut rng = rand::thread_rng();
+    let rules = Rules::default();
+    let difficulties = [Difficulty::Easy, Difficulty::Medium];
+    let players = [*difficulties.choose(&mut rng)?, *difficulties.choose(&mut rng)?];
+    // Stop at a random point so that puzzles come from every stage of the game
+    let stop = rng.gen_range(MIN_MOVES, solver::WIDTH * solver::HEIGHT);
+
+    let mut game = Game::new(rules.clone(), vec![(); 2]);
+    let mut moves = String::new();
+    while moves.len() < stop {
+        let color = game.current_color();
+        let column = ai::choose_move(game.board(), color, rules.win_length, players[moves.len() % 2])?;
+        match game.play_move(Move::Drop(column)) {
+            MoveOutcome::Continue => moves.push(std::char::from_digit(column as u32 + 1, 10)?),
+            // The game ended before reaching the stopping point
+            _ => return None,
+        }
+    }
+    solve(solver, &moves, game.current_color())
+}
+
+/// Returns the position after `moves` as a puzzle if `color`, who is to move, has exactly one
+/// move that wins in the right number of plies
+fn solve(solver: &mut Solver, moves: &str, color: GameCell) -> Option<Puzzle> {
+    let position = Position::from_moves(moves)?;
+    solver.set_deadline(Some(Instant::now() + SOLVE_TIME));
+    let scores = solver.analyze(&position)?;
+
+    let best = scores.iter().flatten().copied().max().filter(|&best| best > 0)?;
+    if scores.iter().filter(|score| score.is_some_and(|score| score > 0)).count() != 1 {
+        return None;
+    }
+
+    let win_in = position.moves_to_win(best)?;
+    if !(MIN_PLIES..=MAX_PLIES).contains(&(2 * win_in - 1)) {
+        return None;
+    }
+
+    let puzzle = Puzzle {
+        moves: String::from(moves),
+        win_in,
+        rating: puzzle::rating(solver, moves)?,
+    };
+    debug_assert_eq!(puzzle.color(), color);
+    Some(puzzle)
+}
@@ -1,3 +1,8 @@
+use crate::game::{GameConfig, MULTIPLAYER_BOARD_HEIGHT, MULTIPLAYER_BOARD_WIDTH};
+use crate::notation;
+use crate::settings::Setting;
+use four_in_a_row_core::board::{MAX_BOARD_SIZE, MIN_BOARD_SIZE, PLAYER_COLORS};
+use four_in_a_row_core::game::{RulesError, MIN_WIN_LENGTH};
 use serenity::model::prelude::*;
 
 /// Represents ways a message may fail to be parsed as a valid command
@@ -9,8 +14,18 @@ pub enum ParseCommandError {
     BotAuthor,
     /// The target of this command could not be found or was not specified
     InvalidTargetUser,
+    /// More users were challenged than there are colours to play with
+    TooManyPlayers,
     /// This command doesn't exist or is malformed
     InvalidCommand(String),
+    /// An argument to this command could not be understood
+    InvalidArgument(String),
+    /// The position a game was asked to start from can't be played, for the given reason
+    InvalidPosition(String),
+    /// This option only applies to games against the bot, and the bot isn't playing
+    BotOnlyOption(String),
+    /// The options can't be combined into a game, for the given reason
+    InvalidRules(RulesError),
 }
 
 /// Represents a command sent by a user
@@ -18,9 +33,105 @@ pub enum ParseCommandError {
 pub enum Command {
     Challenge {
         channel: ChannelId,
+        guild: Option<GuildId>,
         challenger: User,
-        opponent: User,
+        /// Everyone else who will play, in the order they were mentioned. In team games the
+        /// challenger teams up with the first of them.
+        opponents: Vec<User>,
+        config: GameConfig,
     },
+    /// Start a game against the bot
+    Ai {
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        challenger: User,
+        config: GameConfig,
+    },
+    /// Privately suggest a move to a player of the game in this channel
+    Hint {
+        channel: ChannelId,
+        player: User,
+    },
+    /// Pop a piece out of the bottom of a column in a PopOut game
+    Pop {
+        channel: ChannelId,
+        player: User,
+        column: usize,
+    },
+    /// Place a piece at a coordinate in a gravity-free game
+    Place {
+        channel: ChannelId,
+        player: User,
+        column: usize,
+        row: usize,
+    },
+    /// Give up the game in this channel
+    Resign {
+        channel: ChannelId,
+        player: User,
+    },
+    /// Offer the other players of the game in this channel a draw
+    Draw {
+        channel: ChannelId,
+        player: User,
+    },
+    /// Ask to take back the last move in the game in this channel
+    Undo {
+        channel: ChannelId,
+        player: User,
+    },
+    /// Review the moves of a finished game
+    Analyze {
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        author: User,
+        game_id: usize,
+    },
+    /// Try today's puzzle
+    Puzzle {
+        channel: ChannelId,
+        player: User,
+    },
+    /// Step through a finished game or an uploaded game record
+    Replay {
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        author: User,
+        source: ReplaySource,
+    },
+    /// Download the record of a finished game
+    Export {
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        author: User,
+        game_id: usize,
+    },
+    /// Change a setting for the whole guild
+    Settings {
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        author: User,
+        setting: Setting,
+    },
+}
+
+/// Where the game to replay comes from
+#[derive(Debug, Clone)]
+pub enum ReplaySource {
+    /// A finished game, by its ID in the guild (or DM channel) the command was sent in
+    Game(usize),
+    /// A file in the format written by `c4!export`
+    Upload(Attachment),
+}
+
+impl PartialEq for ReplaySource {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ReplaySource::Game(a), ReplaySource::Game(b)) => a == b,
+            (ReplaySource::Upload(a), ReplaySource::Upload(b)) => a.id == b.id,
+            _ => false,
+        }
+    }
 }
 
 pub fn parse_command(msg: &Message) -> Result<Command, ParseCommandError> {
@@ -31,27 +142,163 @@ pub fn parse_command(msg: &Message) -> Result<Command, ParseCommandError> {
         return Err(ParseCommandError::BotAuthor);
     }
 
-    let command_name = msg
+    let mut args = msg
         .content
         .get("c4!".len()..)
         .unwrap() // We already confirmed that the message has the prefix
-        .split_ascii_whitespace()
-        .next();
+        .split_ascii_whitespace();
+    let command_name = args.next();
 
     match command_name {
         Some("challenge") => {
             let channel = msg.channel_id;
             let challenger = msg.author.clone();
-            let opponent = msg
-                .mentions
-                .get(0)
-                .ok_or(ParseCommandError::InvalidTargetUser)?
-                .clone();
+            let args = args.collect::<Vec<_>>();
+            // Discord doesn't list mentions in the order they were written, which decides the teams
+            let mut opponents = Vec::<User>::new();
+            for id in args.iter().filter_map(|arg| parse_user_mention(arg)) {
+                if let Some(user) = msg.mentions.iter().find(|u| u.id == id) {
+                    if user.id != challenger.id && !opponents.iter().any(|o| o.id == user.id) {
+                        opponents.push(user.clone());
+                    }
+                }
+            }
+            if opponents.is_empty() {
+                return Err(ParseCommandError::InvalidTargetUser);
+            }
+            if opponents.len() >= PLAYER_COLORS.len() {
+                return Err(ParseCommandError::TooManyPlayers);
+            }
+            // The bot's own ID isn't known here, but only a bot account can be it
+            let against_bot = opponents.iter().any(|o| o.bot);
+            let config = parse_game_config(
+                args.into_iter().filter(|arg| !is_mention(arg)),
+                opponents.len() + 1,
+                against_bot,
+            )?;
 
             Ok(Command::Challenge {
                 channel,
+                guild: msg.guild_id,
                 challenger,
-                opponent,
+                opponents,
+                config,
+            })
+        }
+
+        Some("ai") => {
+            let channel = msg.channel_id;
+            let challenger = msg.author.clone();
+            let config = parse_game_config(args, 2, true)?;
+
+            Ok(Command::Ai {
+                channel,
+                guild: msg.guild_id,
+                challenger,
+                config,
+            })
+        }
+
+        Some("hint") => Ok(Command::Hint {
+            channel: msg.channel_id,
+            player: msg.author.clone(),
+        }),
+
+        Some("pop") => {
+            let arg = args.next().unwrap_or("");
+            let column = match arg.parse::<usize>() {
+                Ok(column) if (1..=MAX_BOARD_SIZE).contains(&column) => column - 1,
+                _ => return Err(ParseCommandError::InvalidArgument(String::from(arg))),
+            };
+
+            Ok(Command::Pop {
+                channel: msg.channel_id,
+                player: msg.author.clone(),
+                column,
+            })
+        }
+
+        Some("play") => {
+            let arg = args.next().unwrap_or("");
+            let (column, row) =
+                parse_coordinate(arg).ok_or_else(|| ParseCommandError::InvalidArgument(String::from(arg)))?;
+
+            Ok(Command::Place {
+                channel: msg.channel_id,
+                player: msg.author.clone(),
+                column,
+                row,
+            })
+        }
+
+        Some("resign") => Ok(Command::Resign {
+            channel: msg.channel_id,
+            player: msg.author.clone(),
+        }),
+
+        Some("draw") => Ok(Command::Draw {
+            channel: msg.channel_id,
+            player: msg.author.clone(),
+        }),
+
+        Some("undo") => Ok(Command::Undo {
+            channel: msg.channel_id,
+            player: msg.author.clone(),
+        }),
+
+        Some("analyze") => Ok(Command::Analyze {
+            channel: msg.channel_id,
+            guild: msg.guild_id,
+            author: msg.author.clone(),
+            game_id: parse_game_id(args.next().unwrap_or(""))?,
+        }),
+
+        Some("puzzle") => Ok(Command::Puzzle {
+            channel: msg.channel_id,
+            player: msg.author.clone(),
+        }),
+
+        Some("replay") => {
+            let source = match (args.next(), msg.attachments.first()) {
+                (Some(arg), _) => ReplaySource::Game(parse_game_id(arg)?),
+                (None, Some(attachment)) => ReplaySource::Upload(attachment.clone()),
+                (None, None) => return Err(ParseCommandError::InvalidArgument(String::new())),
+            };
+
+            Ok(Command::Replay {
+                channel: msg.channel_id,
+                guild: msg.guild_id,
+                author: msg.author.clone(),
+                source,
+            })
+        }
+
+        Some("export") => Ok(Command::Export {
+            channel: msg.channel_id,
+            guild: msg.guild_id,
+            author: msg.author.clone(),
+            game_id: parse_game_id(args.next().unwrap_or(""))?,
+        }),
+
+        Some("settings") => {
+            let setting = match (args.next(), args.next()) {
+                (Some("hints"), Some(policy)) => Setting::Hints(
+                    policy
+                        .parse()
+                        .map_err(|_| ParseCommandError::InvalidArgument(String::from(policy)))?,
+                ),
+                (name, _) => {
+                    return Err(ParseCommandError::InvalidArgument(String::from(
+                        name.unwrap_or(""),
+                    )))
+                }
+            };
+
+            Ok(Command::Settings {
+                channel: msg.channel_id,
+                guild: msg.guild_id,
+                author: msg.author.clone(),
+                setting,
             })
         }
 
@@ -60,3 +307,267 @@ pub fn parse_command(msg: &Message) -> Result<Command, ParseCommandError> {
         ))),
     }
 }
+
+/// Returns true if `arg` is a user, role or channel mention (e.g. `<@1234>`)
+fn is_mention(arg: &str) -> bool {
+    arg.starts_with('<') && arg.ends_with('>')
+}
+
+/// Parses the number of a finished game, e.g. `12` or `#12`
+fn parse_game_id(arg: &str) -> Result<usize, ParseCommandError> {
+    arg.trim_start_matches('#')
+        .parse()
+        .map_err(|_| ParseCommandError::InvalidArgument(String::from(arg)))
+}
+
+/// Returns the ID of the user mentioned by `arg`, if it is a user mention (e.g. `<@1234>` or `<@!1234>`)
+fn parse_user_mention(arg: &str) -> Option<UserId> {
+    let id = arg.strip_prefix("<@")?.strip_suffix('>')?;
+    id.trim_start_matches('!').parse().ok().map(UserId)
+}
+
+/// Builds a game configuration out of the options given to a challenge between `players` people,
+/// one of whom may be the bot.
+///
+/// Currently recognised options:
+/// * `<width>x<height>`: the size of the board, e.g. `8x7`
+/// * `connect<n>`: the number of pieces in a row needed to win, e.g. `connect5`
+/// * `easy`, `medium`, `hard` or `perfect`: how strongly the bot plays. Only allowed against the bot.
+/// * `standard`, `popout`, `pop10`, `misere`, `anywhere` or `toroidal`: which rules to play by
+/// * `rated`: marks the game as rated rather than casual
+/// * `hints:<allowed|casual|disabled>`: whether players may ask for hints
+/// * `teams`: splits four players into two teams of two
+/// * `<minutes>+<seconds>`: gives each player a clock with an increment, e.g. `3+2`
+/// * `position:<moves or rows>`: starts from a position given as a move string (e.g. `4453`) or
+///   as rows from top to bottom separated by `/`
+fn parse_game_config<'a>(
+    args: impl Iterator<Item = &'a str>,
+    players: usize,
+    against_bot: bool,
+) -> Result<GameConfig, ParseCommandError> {
+    let mut config = GameConfig::default();
+    // Give everyone more room by default when there are more than two players
+    if players > 2 {
+        config.width = MULTIPLAYER_BOARD_WIDTH;
+        config.height = MULTIPLAYER_BOARD_HEIGHT;
+    }
+    let mut position_arg = None;
+
+    for arg in args {
+        if let Some((width, height)) = parse_board_size(arg) {
+            config.width = width;
+            config.height = height;
+        } else if let Some(win_length) = parse_win_length(arg) {
+            config.win_length = win_length;
+        } else if let Ok(difficulty) = arg.parse() {
+            if !against_bot {
+                return Err(ParseCommandError::BotOnlyOption(String::from(arg)));
+            }
+            config.ai_difficulty = difficulty;
+        } else if let Ok(variant) = arg.parse() {
+            config.variant = variant;
+        } else if arg.eq_ignore_ascii_case("rated") {
+            config.rated = true;
+        } else if let Some(Ok(policy)) = arg.strip_prefix("hints:").map(str::parse) {
+            config.hints = policy;
+        } else if arg.eq_ignore_ascii_case("teams") {
+            config.teams = true;
+        } else if let Ok(time_control) = arg.parse() {
+            config.time_control = Some(time_control);
+        } else if let Some(position) = arg.strip_prefix("position:") {
+            position_arg = Some(position);
+        } else {
+            return Err(ParseCommandError::InvalidArgument(String::from(arg)));
+        }
+    }
+
+    config.rules().check(players).map_err(ParseCommandError::InvalidRules)?;
+
+    // The board size and win length need to be known before the position can be checked
+    if let Some(position) = position_arg {
+        let board = notation::parse_position(position, &config)
+            .map_err(|e| ParseCommandError::InvalidPosition(e.to_string()))?;
+        config.setup = Some(board);
+        config.rules().check(players).map_err(ParseCommandError::InvalidRules)?;
+    }
+
+    Ok(config)
+}
+
+/// Parses a board size of the form `<width>x<height>`, returning `None` if it is malformed or out of bounds
+fn parse_board_size(arg: &str) -> Option<(usize, usize)> {
+    let mut dims = arg.splitn(2, ['x', 'X']);
+    let width = dims.next()?.parse::<usize>().ok()?;
+    let height = dims.next()?.parse::<usize>().ok()?;
+
+    let valid = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+    if valid.contains(&width) && valid.contains(&height) {
+        Some((width, height))
+    } else {
+        None
+    }
+}
+
+/// Parses a cell of the form `<column letter><row number>`, e.g. `c4`, returning `None` if it is
+/// malformed or out of bounds. Rows are counted from the bottom.
+fn parse_coordinate(arg: &str) -> Option<(usize, usize)> {
+    let mut chars = arg.chars();
+    let letter = chars.next()?.to_ascii_lowercase();
+    if !letter.is_ascii_lowercase() {
+        return None;
+    }
+    let column = (letter as u8 - b'a') as usize;
+    let row = chars.as_str().parse::<usize>().ok()?;
+
+    if column < MAX_BOARD_SIZE && (1..=MAX_BOARD_SIZE).contains(&row) {
+        Some((column, row - 1))
+    } else {
+        None
+    }
+}
+
+/// Parses a win length of the form `connect<n>`, returning `None` if it is malformed or too short
+fn parse_win_length(arg: &str) -> Option<usize> {
+    let n = arg
+        .to_ascii_lowercase()
+        .strip_prefix("connect")?
+        .parse::<usize>()
+        .ok()?;
+
+    if (MIN_WIN_LENGTH..=MAX_BOARD_SIZE).contains(&n) {
+        Some(n)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::TimeControl;
+    use four_in_a_row_core::board::GameCell;
+    use four_in_a_row_core::game::Variant;
+
+    /// Parses the options of a challenge given as one string
+    fn config(args: &str, players: usize, against_bot: bool) -> Result<GameConfig, ParseCommandError> {
+        parse_game_config(args.split_ascii_whitespace(), players, against_bot)
+    }
+
+    #[test]
+    fn board_sizes_are_bounded() {
+        assert_eq!(parse_board_size("8x7"), Some((8, 7)));
+        assert_eq!(parse_board_size("4X9"), Some((4, 9)));
+        assert_eq!(parse_board_size("3x6"), None);
+        assert_eq!(parse_board_size("7x10"), None);
+        assert_eq!(parse_board_size("7x"), None);
+        assert_eq!(parse_board_size("7by6"), None);
+
+        let game = config("5x4", 2, false).unwrap();
+        assert_eq!((game.width, game.height), (5, 4));
+        assert_eq!(config("10x6", 2, false), Err(ParseCommandError::InvalidArgument(String::from("10x6"))));
+        // More players get a bigger board unless they ask for one
+        let game = config("", 3, false).unwrap();
+        assert_eq!((game.width, game.height), (MULTIPLAYER_BOARD_WIDTH, MULTIPLAYER_BOARD_HEIGHT));
+    }
+
+    #[test]
+    fn win_length_fits_the_board() {
+        assert_eq!(parse_win_length("connect5"), Some(5));
+        assert_eq!(parse_win_length("Connect3"), Some(3));
+        assert_eq!(parse_win_length("connect2"), None);
+        assert_eq!(parse_win_length("connect10"), None);
+        assert_eq!(parse_win_length("connect"), None);
+
+        assert_eq!(config("9x4 connect9", 2, false).unwrap().win_length, 9);
+        assert_eq!(config("connect8", 2, false), Err(ParseCommandError::InvalidRules(RulesError::WinLength)));
+        assert_eq!(config("5x5 connect6", 2, false), Err(ParseCommandError::InvalidRules(RulesError::WinLength)));
+    }
+
+    #[test]
+    fn pop_ten_needs_room_to_collect() {
+        assert_eq!(config("pop10 4x5", 2, false).unwrap().variant, Variant::PopTen);
+        assert_eq!(config("pop10 4x4", 2, false), Err(ParseCommandError::InvalidRules(RulesError::PopTenBoard)));
+    }
+
+    #[test]
+    fn toroidal_lines_fit_around_the_board() {
+        assert_eq!(config("toroidal 5x6 connect5", 2, false).unwrap().variant, Variant::Toroidal);
+        assert_eq!(
+            config("toroidal 5x6 connect6", 2, false),
+            Err(ParseCommandError::InvalidRules(RulesError::WrappingLine))
+        );
+    }
+
+    #[test]
+    fn teams_need_four_players() {
+        assert!(config("teams", 4, false).unwrap().teams);
+        assert_eq!(config("teams", 3, false), Err(ParseCommandError::InvalidRules(RulesError::Teams)));
+        assert_eq!(config("teams", 2, false), Err(ParseCommandError::InvalidRules(RulesError::Teams)));
+    }
+
+    #[test]
+    fn some_variants_are_for_two_players() {
+        assert_eq!(config("anywhere", 3, false).unwrap().variant, Variant::Anywhere);
+        assert_eq!(
+            config("misere", 3, false),
+            Err(ParseCommandError::InvalidRules(RulesError::TwoPlayersOnly(Variant::Misere)))
+        );
+        assert_eq!(
+            config("popout teams", 4, false),
+            Err(ParseCommandError::InvalidRules(RulesError::TwoPlayersOnly(Variant::PopOut)))
+        );
+    }
+
+    #[test]
+    fn difficulty_is_only_for_the_bot() {
+        assert_eq!(config("hard", 2, true).unwrap().ai_difficulty, "hard".parse().unwrap());
+        assert_eq!(config("hard", 2, false), Err(ParseCommandError::BotOnlyOption(String::from("hard"))));
+    }
+
+    #[test]
+    fn positions_are_checked() {
+        let game = config("position:4453", 2, false).unwrap();
+        assert_eq!(game.setup.unwrap().pieces(GameCell::Red).count_ones(), 2);
+        assert!(config("position:4453 teams", 4, false).is_ok());
+
+        assert!(matches!(config("position:48", 2, false), Err(ParseCommandError::InvalidPosition(_))));
+        assert!(matches!(config("position:1212121", 2, false), Err(ParseCommandError::InvalidPosition(_))));
+        assert!(matches!(config("position:4a5", 2, false), Err(ParseCommandError::InvalidPosition(_))));
+        assert_eq!(
+            config("position:44 pop10", 2, false),
+            Err(ParseCommandError::InvalidRules(RulesError::VariantSetup(Variant::PopTen)))
+        );
+        assert_eq!(
+            config("position:44 anywhere", 2, false),
+            Err(ParseCommandError::InvalidRules(RulesError::VariantSetup(Variant::Anywhere)))
+        );
+        assert_eq!(
+            config("position:44", 3, false),
+            Err(ParseCommandError::InvalidRules(RulesError::MultiplayerSetup))
+        );
+    }
+
+    #[test]
+    fn coordinates_count_rows_from_the_bottom() {
+        assert_eq!(parse_coordinate("a1"), Some((0, 0)));
+        assert_eq!(parse_coordinate("C4"), Some((2, 3)));
+        assert_eq!(parse_coordinate("i9"), Some((8, 8)));
+        assert_eq!(parse_coordinate("j1"), None);
+        assert_eq!(parse_coordinate("a0"), None);
+        assert_eq!(parse_coordinate("a10"), None);
+        assert_eq!(parse_coordinate("4c"), None);
+        assert_eq!(parse_coordinate(""), None);
+    }
+
+    #[test]
+    fn time_controls_are_bounded() {
+        let game = config("5+3", 2, false).unwrap();
+        assert_eq!(game.time_control, Some("5+3".parse::<TimeControl>().unwrap()));
+        assert!("60+60".parse::<TimeControl>().is_ok());
+        assert!("1+0".parse::<TimeControl>().is_ok());
+        assert_eq!("61+0".parse::<TimeControl>(), Err(()));
+        assert_eq!("5+61".parse::<TimeControl>(), Err(()));
+        assert_eq!("5-3".parse::<TimeControl>(), Err(()));
+        assert_eq!(config("0+5", 2, false), Err(ParseCommandError::InvalidArgument(String::from("0+5"))));
+    }
+}
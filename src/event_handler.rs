@@ -2,6 +2,7 @@ use serenity::{async_trait, client::EventHandler, model::prelude::*, prelude::*}
 
 use crate::command::ParseCommandError;
 
+#[derive(Default)]
 pub struct Handler;
 
 impl Handler {
@@ -45,6 +46,39 @@ impl EventHandler for Handler {
                         .await;
                 }
 
+                ParseCommandError::TooManyPlayers => {
+                    let _ = new_message
+                        .reply(
+                            &ctx.http,
+                            format!("Games can have at most {} players.", four_in_a_row_core::board::PLAYER_COLORS.len()),
+                        )
+                        .await;
+                }
+
+                ParseCommandError::InvalidArgument(arg) => {
+                    let _ = new_message
+                        .reply(&ctx.http, format!("I don't understand the option `{}`.", arg))
+                        .await;
+                }
+
+                ParseCommandError::InvalidPosition(reason) => {
+                    let _ = new_message
+                        .reply(&ctx.http, format!("That position can't be played: {}.", reason))
+                        .await;
+                }
+
+                ParseCommandError::BotOnlyOption(arg) => {
+                    let _ = new_message
+                        .reply(&ctx.http, format!("The option `{}` is only for games against me.", arg))
+                        .await;
+                }
+
+                ParseCommandError::InvalidRules(reason) => {
+                    let _ = new_message
+                        .reply(&ctx.http, format!("That game can't be played: {}.", reason))
+                        .await;
+                }
+
                 ParseCommandError::InvalidCommand(_) => {
                     let _ = new_message.react(&ctx.http, '❓').await;
                 }
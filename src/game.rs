@@ -1,19 +1,64 @@
-use crate::command::Command;
+use crate::analysis;
+use crate::command::{Command, ReplaySource};
+use crate::hint;
+use crate::notation::{self, ImportedGame};
+use crate::puzzle::{self, Streak};
+use crate::settings::{self, GuildSettings, HintPolicy, Setting};
+use crate::storage::Storage;
+use four_in_a_row_core::ai;
+use four_in_a_row_core::board::{Board, GameCell, PLAYER_COLORS};
+use four_in_a_row_core::game::{color_index, Elimination, Game, Move, MoveOutcome, Phase, Rules, Variant, POP_TEN_TARGET};
 use futures::stream::StreamExt;
+use rand::seq::SliceRandom;
+use serenity::collector::CollectReaction;
 use serenity::model::prelude::*;
 use serenity::prelude::*;
-use smallvec::SmallVec;
-use std::{collections::hash_map::{Entry, HashMap}};
-use itertools::Itertools;
+use std::{collections::{hash_map::{Entry, HashMap}, HashSet}, time::{Duration, Instant}};
 
-const BOARD_HEIGHT: usize = 6;
-const BOARD_WIDTH: usize = 7;
+/// Default number of rows on a board
+pub const DEFAULT_BOARD_HEIGHT: usize = 6;
+/// Default number of columns on a board
+pub const DEFAULT_BOARD_WIDTH: usize = 7;
+/// Default number of rows on a board for more than two players
+pub const MULTIPLAYER_BOARD_HEIGHT: usize = 7;
+/// Default number of columns on a board for more than two players
+pub const MULTIPLAYER_BOARD_WIDTH: usize = 9;
+/// Default number of pieces in a row needed to win
+pub const DEFAULT_WIN_LENGTH: usize = 4;
+/// Longest time bank a player may start with, in minutes
+pub const MAX_CLOCK_MINUTES: u64 = 60;
+/// Longest increment that may be added after each move, in seconds
+pub const MAX_INCREMENT_SECONDS: u64 = 60;
+/// The bot thinks for at most this fraction of its remaining time on each move
+const BOT_CLOCK_SHARE: u32 = 10;
 
 const DENY_CHALLENGE: char = '❌';
 const ACCEPT_CHALLENGE: char = '✅';
-const RED_PIECE: char = '🔴';
-const YELLOW_PIECE: char = '🟡';
-const BLANK_CELL: char = '⚫';
+/// Replaces the pieces of the winning line on the final board
+const WINNING_PIECE: char = '⭐';
+const HINT: char = '💡';
+const BOARD_CORNER: char = '🔲';
+/// Shown next to each player's remaining time in games with a clock
+const CLOCK: &str = "\u{23f1}\u{fe0f}";
+/// Drawn on both sides of toroidal boards, where lines carry on from one edge to the other
+const WRAP_EDGE: char = '🔁';
+
+/// Reactions for stepping through a replay: first, previous, next and last position
+const REPLAY_CONTROLS: &[&str] = &[
+    "\u{23ee}\u{fe0f}", // ⏮️
+    "\u{25c0}\u{fe0f}", // ◀️
+    "\u{25b6}\u{fe0f}", // ▶️
+    "\u{23ed}\u{fe0f}", // ⏭️
+];
+/// How long a replay keeps responding to its controls after they were last used
+const REPLAY_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long a player has to make each move of a puzzle
+const PUZZLE_MOVE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Shown once for each point of a puzzle's difficulty rating
+const PUZZLE_STAR: char = '⭐';
+
+/// Letters labelling the columns of a board, for games where pieces can go anywhere
+const COLUMN_LABELS: &[char] = &['🇦', '🇧', '🇨', '🇩', '🇪', '🇫', '🇬', '🇭', '🇮'];
 
 const NUMBER_EMOTES: &[&str] = &[
     "\u{31}\u{fe0f}\u{20e3}", // 1
@@ -29,6 +74,17 @@ const NUMBER_EMOTES: &[&str] = &[
 
 pub struct GameModel {
     games: HashMap<ChannelId, tokio::sync::mpsc::Sender<GameAction>>,
+    guild_settings: HashMap<GuildId, GuildSettings>,
+    /// Where every game that has finished with a result is kept, along with the streaks and settings
+    storage: Storage,
+    /// Each player's run of daily puzzles solved
+    streaks: HashMap<UserId, Streak>,
+}
+
+impl Default for GameModel {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TypeMapKey for GameModel {
@@ -37,8 +93,12 @@ impl TypeMapKey for GameModel {
 
 impl GameModel {
     pub fn new() -> Self {
+        let storage = Storage::default();
         GameModel {
             games: HashMap::new(),
+            guild_settings: storage.guild_settings(),
+            streaks: storage.streaks(),
+            storage,
         }
     }
 
@@ -52,15 +112,230 @@ impl GameModel {
         match command {
             Command::Challenge {
                 channel,
+                guild,
                 challenger,
-                opponent,
-            } => self.handle_challenge(ctx, channel, challenger, opponent).await,
+                opponents,
+                config,
+            } => {
+                let config = self.apply_guild_settings(guild, config);
+                self.handle_challenge(ctx, channel, guild, challenger, opponents, config).await
+            }
+
+            Command::Ai {
+                channel,
+                guild,
+                challenger,
+                config,
+            } => {
+                let config = self.apply_guild_settings(guild, config);
+                let bot = User::from(ctx.cache.current_user().await);
+                self.handle_challenge(ctx, channel, guild, challenger, vec![bot], config).await
+            }
+
+            Command::Hint { channel, player } => {
+                self.send_action(ctx, channel, player.id, GameAction::Hint(player)).await
+            }
+
+            Command::Pop { channel, player, column } => {
+                self.send_action(ctx, channel, player.id, GameAction::Pop(player, column)).await
+            }
+
+            Command::Place { channel, player, column, row } => {
+                self.send_action(ctx, channel, player.id, GameAction::Place(player, column, row)).await
+            }
+
+            Command::Resign { channel, player } => {
+                self.send_action(ctx, channel, player.id, GameAction::Resign(player)).await
+            }
+
+            Command::Draw { channel, player } => {
+                self.send_action(ctx, channel, player.id, GameAction::OfferDraw(player)).await
+            }
+
+            Command::Undo { channel, player } => {
+                self.send_action(ctx, channel, player.id, GameAction::Undo(player)).await
+            }
+
+            Command::Analyze { channel, guild, author, game_id } => {
+                match self.find_game(RecordScope::new(guild, channel), game_id) {
+                    // Analysis takes a while, so don't hold on to the game model while it runs
+                    Some(game) => {
+                        tokio::spawn(analyze_game(ctx, channel, game_id, game));
+                    }
+                    None => {
+                        let _ = channel
+                            .send_message(&ctx, |msg| {
+                                msg.content(format!("{} There is no finished game #{}.", author.mention(), game_id))
+                            })
+                            .await;
+                    }
+                }
+            }
+
+            Command::Puzzle { channel, player } => {
+                tokio::spawn(puzzle_session(ctx, channel, player));
+            }
+
+            Command::Replay { channel, guild, author, source } => match source {
+                ReplaySource::Game(game_id) => match self.find_game(RecordScope::new(guild, channel), game_id) {
+                    Some(game) => {
+                        let title = format!("game #{}", game_id);
+                        tokio::spawn(replay_game(ctx, channel, title, game));
+                    }
+                    None => {
+                        let _ = channel
+                            .send_message(&ctx, |msg| {
+                                msg.content(format!("{} There is no finished game #{}.", author.mention(), game_id))
+                            })
+                            .await;
+                    }
+                },
+                ReplaySource::Upload(attachment) => {
+                    // Don't hold on to the game model while the file downloads
+                    tokio::spawn(async move {
+                        let game = attachment
+                            .download()
+                            .await
+                            .ok()
+                            .and_then(|bytes| String::from_utf8(bytes).ok())
+                            .and_then(|text| notation::import(&text));
+                        match game {
+                            Some(game) => replay_game(ctx, channel, attachment.filename, game).await,
+                            None => {
+                                let _ = channel
+                                    .send_message(&ctx, |msg| {
+                                        msg.content(format!(
+                                            "{} That file isn't a game record from `c4!export`.",
+                                            author.mention()
+                                        ))
+                                    })
+                                    .await;
+                            }
+                        }
+                    });
+                }
+            },
+
+            Command::Export { channel, guild, author, game_id } => {
+                match self.storage.game(RecordScope::new(guild, channel), game_id) {
+                    Some(export) => {
+                        let filename = format!("game-{}.txt", game_id);
+                        let files = vec![(export.as_bytes(), &filename[..])];
+                        let _ = channel
+                            .send_files(&ctx.http, files, |msg| {
+                                msg.content(format!("{} Here is game #{}.", author.mention(), game_id))
+                            })
+                            .await;
+                    }
+                    None => {
+                        let _ = channel
+                            .send_message(&ctx, |msg| {
+                                msg.content(format!("{} There is no finished game #{}.", author.mention(), game_id))
+                            })
+                            .await;
+                    }
+                }
+            }
+
+            Command::Settings {
+                channel,
+                guild,
+                author,
+                setting,
+            } => {
+                // Checking permissions takes a couple of API calls, so don't hold on to the game
+                // model while they run
+                tokio::spawn(change_setting(ctx, channel, guild, author, setting));
+            }
         }
     }
 
+    /// Passes an action on to the game in `channel`, telling `author` if there isn't one
+    async fn send_action(&mut self, ctx: Context, channel: ChannelId, author: UserId, action: GameAction) {
+        match self.games.get_mut(&channel) {
+            // Don't wait for space in the queue while holding the lock on the game model
+            Some(game) => {
+                let _ = game.try_send(action);
+            }
+            None => {
+                let _ = channel
+                    .send_message(&ctx, |msg| {
+                        msg.content(format!("{} There is no game in this channel.", author.mention()))
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Records an attempt at the puzzle for `day`, which counts as a failure until
+    /// `finish_puzzle` says otherwise. Returns whether the attempt counts towards the player's
+    /// streak.
+    fn start_puzzle(&mut self, player: UserId, day: i64) -> bool {
+        let counted = self.streaks.entry(player).or_default().attempt(day);
+        if counted {
+            self.save_streaks();
+        }
+        counted
+    }
+
+    /// Records how an attempt at the puzzle for `day` went and returns the player's streak
+    fn finish_puzzle(&mut self, player: UserId, day: i64, solved: bool) -> Streak {
+        let streak = self.streaks.entry(player).or_default();
+        if !solved {
+            return *streak;
+        }
+        streak.solve(day);
+        let streak = *streak;
+        self.save_streaks();
+        streak
+    }
+
+    fn save_streaks(&self) {
+        if let Err(e) = self.storage.save_streaks(&self.streaks) {
+            log::error!("Failed to save puzzle streaks: {}", e);
+        }
+    }
+
+    /// Stores a finished game and returns its ID within `scope`, or `None` if it couldn't be saved
+    fn add_record(&mut self, scope: RecordScope, record: GameRecord) -> Option<usize> {
+        match self.storage.add_game(scope, &record) {
+            Ok(game_id) => Some(game_id),
+            Err(e) => {
+                log::error!("Failed to save a finished game: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Looks up a finished game by its ID within `scope`
+    fn find_game(&self, scope: RecordScope, game_id: usize) -> Option<ImportedGame> {
+        notation::import(&self.storage.game(scope, game_id)?)
+    }
+
+    /// Restricts a game's configuration to what the guild it is played in allows
+    fn apply_guild_settings(&self, guild: Option<GuildId>, mut config: GameConfig) -> GameConfig {
+        if let Some(guild) = guild {
+            let settings = self.guild_settings.get(&guild).cloned().unwrap_or_default();
+            config.hints = config.hints.min(settings.hints);
+        }
+        config
+    }
+
     /// Sends a message indicating that a challenge has been made, and spawns a task to handle this game.
-    async fn handle_challenge(&mut self, ctx: Context, channel: ChannelId, challenger: User, opponent: User) {
-        log::info!("Challenge from {} to {} on {}", challenger.id, opponent.id, channel);
+    async fn handle_challenge(
+        &mut self,
+        ctx: Context,
+        channel: ChannelId,
+        guild: Option<GuildId>,
+        challenger: User,
+        opponents: Vec<User>,
+        config: GameConfig,
+    ) {
+        let opponent_mentions = mention_list(opponents.iter().map(|o| o.id));
+        log::info!("Challenge from {} to {} on {}", challenger.id, opponent_mentions, channel);
+
+        let bot_id = ctx.cache.current_user_id().await;
+        let against_bot = opponents.iter().any(|o| o.id == bot_id);
         match self.games.entry(channel) {
             Entry::Occupied(_) => {
                 let _ = channel
@@ -73,15 +348,65 @@ impl GameModel {
                     .await;
             }
 
+            Entry::Vacant(_) if against_bot && (opponents.len() > 1 || !config.variant.engine_support()) => {
+                let variants = Variant::ALL
+                    .iter()
+                    .filter(|variant| variant.engine_support())
+                    .map(|variant| variant.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" or ");
+                let _ = channel
+                    .send_message(&ctx, |msg| {
+                        msg.content(format!(
+                            "{} I only know how to play one-on-one games of {}.",
+                            challenger.mention(),
+                            variants
+                        ))
+                    })
+                    .await;
+            }
+
+            // The bot always accepts challenges against itself
+            Entry::Vacant(e) if against_bot => {
+                let (send, recv) = tokio::sync::mpsc::channel(4);
+                e.insert(send.clone());
+
+                tokio::spawn(async move {
+                    let mut players = opponents;
+                    players.insert(0, challenger);
+                    game(recv, send, ctx.clone(), channel, guild, players, config).await;
+
+                    // Remove this thread's channel from the `games` map
+                    ctx.data.write().await.get_mut::<GameModel>().unwrap().close_game(channel).await;
+                });
+            }
+
             Entry::Vacant(e) => {
+                // The challenger teams up with whoever they mentioned first
+                let teams = if config.teams {
+                    format!(
+                        "\nTeams: {} and {} vs {} and {}",
+                        challenger.mention(),
+                        opponents[0].mention(),
+                        opponents[1].mention(),
+                        opponents[2].mention()
+                    )
+                } else {
+                    String::new()
+                };
                 let challenge_message = channel
                     .send_message(&ctx, |msg| {
                         msg.content(format!(
-                            "{} has been challenged to a game by {}!\n\nThis invite will expire in 60 seconds.",
-                            opponent.mention(),
-                            challenger.mention()
+                            "{} {} been challenged to a {}x{} game of Connect {} by {}!{}\n\nThis invite will expire in 60 seconds.",
+                            opponent_mentions,
+                            if opponents.len() > 1 { "have" } else { "has" },
+                            config.width,
+                            config.height,
+                            config.win_length,
+                            challenger.mention(),
+                            teams
                         ))
-                        .reactions([DENY_CHALLENGE, ACCEPT_CHALLENGE].iter().map(|c| c.clone()))
+                        .reactions([DENY_CHALLENGE, ACCEPT_CHALLENGE].iter().copied())
                     })
                     .await;
                 
@@ -91,45 +416,50 @@ impl GameModel {
                 };
 
                 let challenger_id = challenger.id;
-                let opponent_id = opponent.id;
+                let opponent_ids = opponents.iter().map(|o| o.id).collect::<Vec<_>>();
+                let filter_ids = opponent_ids.clone();
                 let mut reaction_stream = challenge_message
                     .await_reactions(&ctx.shard)
                     .filter(move |r| {
-                        r.user_id == Some(challenger_id) || r.user_id == Some(opponent_id)
+                        r.user_id == Some(challenger_id) || r.user_id.is_some_and(|id| filter_ids.contains(&id))
                     })
                     .timeout(std::time::Duration::from_secs(60))
                     .await;
 
                 let (send, recv) = tokio::sync::mpsc::channel(4);
-                e.insert(send);
+                e.insert(send.clone());
 
-                let challenger_id = challenger.id;
-                let opponent_id = opponent.id;
                 let ctx = ctx.clone();
                 tokio::spawn(async move {
                     let mut timed_out = true;
+                    // Every opponent has to accept before the game starts
+                    let mut accepted = HashSet::new();
                     while let Some(reaction) = reaction_stream.next().await {
                         if reaction.is_added() {
                             let r = reaction.as_inner_ref();
+                            let user_id = match r.user_id {
+                                Some(id) => id,
+                                None => continue,
+                            };
                             if r.emoji == ReactionType::from(DENY_CHALLENGE) {
-                                if r.user_id == Some(challenger_id) {
+                                if user_id == challenger_id {
                                     let _ = challenge_message.edit(&ctx.http, |msg| {
                                         msg.content(format!(
                                             "{} has cancelled their challenge against {}",
                                             challenger_id.mention(),
-                                            opponent_id.mention()
+                                            opponent_mentions
                                         ))
                                     })
                                     .await;
                                     reaction_stream.stop();
                                     timed_out = false;
                                     break
-                                } else if r.user_id == Some(opponent_id) {
+                                } else if opponent_ids.contains(&user_id) {
                                     let _ = challenge_message.edit(&ctx.http, |msg| {
                                         msg.content(format!(
                                             "{}'s challenge was declined by {}",
                                             challenger_id.mention(),
-                                            opponent_id.mention()
+                                            user_id.mention()
                                         ))
                                     })
                                     .await;
@@ -137,17 +467,24 @@ impl GameModel {
                                     timed_out = false;
                                     break
                                 }
-                            } else if r.emoji == ReactionType::from(ACCEPT_CHALLENGE) && r.user_id == Some(opponent_id) {
+                            } else if r.emoji == ReactionType::from(ACCEPT_CHALLENGE) && opponent_ids.contains(&user_id) {
+                                accepted.insert(user_id);
+                                if accepted.len() < opponent_ids.len() {
+                                    continue
+                                }
+
                                 let _ = challenge_message.edit(&ctx.http, |msg| {
                                     msg.content(format!(
                                         "{}'s challenge was accepted by {}!",
                                         challenger_id.mention(),
-                                        opponent_id.mention()
+                                        opponent_mentions
                                     ))
                                 })
                                 .await;
                                 reaction_stream.stop();
-                                game(recv, ctx.clone(), channel, challenger, opponent).await;
+                                let mut players = opponents;
+                                players.insert(0, challenger);
+                                game(recv, send, ctx.clone(), channel, guild, players, config).await;
                                 timed_out = false;
                                 break
                             }
@@ -158,7 +495,7 @@ impl GameModel {
                             msg.content(format!(
                                 "*{}'s challenge to {} has timed out.*",
                                 challenger_id.mention(),
-                                opponent_id.mention()
+                                opponent_mentions
                             ))
                         })
                         .await;
@@ -172,54 +509,240 @@ impl GameModel {
     }
 }
 
+/// Applies `setting` to `guild` if `author` is allowed to manage it
+async fn change_setting(ctx: Context, channel: ChannelId, guild: Option<GuildId>, author: User, setting: Setting) {
+    let reply = match guild {
+        None => String::from("Settings can only be changed in a server."),
+        Some(guild) if !settings::can_manage_guild(&ctx, guild, author.id).await => {
+            String::from("You need the `Manage Server` permission to change settings.")
+        }
+        Some(guild) => {
+            let mut data = ctx.data.write().await;
+            let model = data.get_mut::<GameModel>().unwrap();
+            setting.apply(model.guild_settings.entry(guild).or_default());
+            if let Err(e) = model.storage.save_guild_settings(&model.guild_settings) {
+                log::error!("Failed to save guild settings: {}", e);
+            }
+            setting.to_string()
+        }
+    };
+
+    let _ = channel
+        .send_message(&ctx, |msg| msg.content(format!("{} {}", author.mention(), reply)))
+        .await;
+}
+
+/// Mentions each user, separated by commas
+fn mention_list(users: impl Iterator<Item = UserId>) -> String {
+    users.map(|id| id.mention().to_string()).collect::<Vec<_>>().join(", ")
+}
+
 /// Represents a game in progress
 #[derive(Debug)]
 struct GameState {
-    reds_turn: bool,
-    red_player: User,
-    yellow_player: User,
-    board: [[GameCell; BOARD_HEIGHT]; BOARD_WIDTH],
-    config: GameConfig
+    /// The game itself, with the players in turn order
+    game: Game<User>,
+    /// The colour played by the bot, if it is playing
+    ai_color: Option<GameCell>,
+    /// The guild the game is being played in, or `None` in DMs
+    guild: Option<GuildId>,
+    config: GameConfig,
+    /// Number of hints each player (or team) has asked for, indexed by colour
+    hints_used: [u32; PLAYER_COLORS.len()],
+    /// Time each player has left on their clock, indexed like the players. Unused without a time
+    /// control.
+    clocks: Vec<Duration>,
+    /// Why each eliminated player is out, indexed like the players
+    exits: Vec<Option<Exit>>,
+}
+
+/// Why a player was eliminated before the end of a game
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Exit {
+    Resigned,
+    TimedOut,
 }
 
 impl GameState {
-    fn current_player(&self) -> &User {
-        if self.reds_turn {
-            &self.red_player
-        } else {
-            &self.yellow_player
+    /// Creates a game with an empty board sized according to `config`. Players take turns in the
+    /// order given, starting with red. In team games the players must alternate between the teams.
+    ///
+    /// # Panic
+    /// Panics if there are more players than colours
+    fn new(config: GameConfig, players: Vec<User>) -> Self {
+        GameState {
+            clocks: vec![config.time_control.map_or(Duration::default(), |tc| tc.initial); players.len()],
+            exits: vec![None; players.len()],
+            game: Game::new(config.rules(), players),
+            ai_color: None,
+            guild: None,
+            config,
+            hints_used: [0; PLAYER_COLORS.len()],
+        }
+    }
+
+    /// How long the current player has to make their move: whatever is left on their clock, or
+    /// the move timeout in games without one
+    fn time_left(&self) -> Duration {
+        match self.config.time_control {
+            Some(_) => self.clocks[self.game.turn()],
+            None => self.config.move_timeout,
         }
     }
 
+    /// Takes the time `player` spent thinking off their clock, then adds the increment if they
+    /// made a move
+    fn update_clock(&mut self, player: usize, elapsed: Duration, moved: bool) {
+        if let Some(time_control) = self.config.time_control {
+            let clock = &mut self.clocks[player];
+            *clock = clock.saturating_sub(elapsed);
+            if moved {
+                *clock += time_control.increment;
+            }
+        }
+    }
+
+    /// Index into `players` of the given user, if they are still in the game
+    fn active_player(&self, id: UserId) -> Option<usize> {
+        self.game.players()
+            .iter()
+            .enumerate()
+            .position(|(i, p)| p.id == id && !self.game.is_eliminated(i))
+    }
+
+    /// Players still in the game who play a different colour from the player at `index`
+    fn opponents_of(&self, index: usize) -> Vec<UserId> {
+        let color = self.game.color_of(index);
+        (0..self.game.players().len())
+            .filter(|&i| !self.game.is_eliminated(i) && self.game.color_of(i) != color)
+            .map(|i| self.game.players()[i].id)
+            .collect()
+    }
+
     /// Writes out the game state as a discord message
     fn message_content(&self) -> String {
+        let players = (0..self.game.players().len())
+            .map(|i| {
+                format!(
+                    "`[{turn}]` {player}: {piece_emote}\n",
+                    turn = if i == self.game.turn() {"*"} else {" "},
+                    player = self.player_label(i),
+                    piece_emote = self.game.color_of(i),
+                )
+            })
+            .collect::<String>();
+
         format!(
-            "*Move timeout: {move_timeout} seconds*\n\
-             `[{reds_turn}]` {red_player}: {red_piece_emote}\n\
-             `[{yellows_turn}]` {yellow_player}: {yellow_piece_emote}\n\n\
+            "*Connect {win_length}{variant}{teams} | {rated} | {time_control}*\n\
+             {players}\n\
              {board}",
             
-            move_timeout = self.config.move_timeout.as_secs(),
-            red_player = self.red_player.mention(),
-            yellow_player = self.yellow_player.mention(),
-            red_piece_emote = RED_PIECE,
-            yellow_piece_emote = YELLOW_PIECE,
-            reds_turn = if self.reds_turn {"*"} else {" "},
-            yellows_turn = if !self.reds_turn {"*"} else {" "},
+            win_length = self.config.win_length,
+            teams = if self.config.teams { " | Teams" } else { "" },
+            variant = match (self.config.variant, self.game.phase()) {
+                (Variant::Standard, _) => String::new(),
+                (Variant::PopOut, _) => String::from(" | PopOut: `c4!pop <column>`"),
+                (Variant::PopTen, Phase::Setup) => String::from(" | Pop 10: fill the board"),
+                (Variant::PopTen, Phase::Play) => format!(" | Pop 10: pop your pieces to collect {}", POP_TEN_TARGET),
+                (Variant::Misere, _) => String::from(" | Misère: don't complete a line!"),
+                (Variant::Anywhere, _) => String::from(" | Anywhere: `c4!play <column letter><row number>`"),
+                (Variant::Toroidal, _) => format!(" | Toroidal: lines wrap around at {}", WRAP_EDGE),
+            },
+            rated = if self.config.rated { "Rated" } else { "Casual" },
+            time_control = match self.config.time_control {
+                Some(time_control) => format!("Clock: {}", time_control),
+                None => format!("Move timeout: {} seconds", self.config.move_timeout.as_secs()),
+            },
+            players = players,
             board = self.display_board(),
         )
     }
 
+    /// Mentions the player at `index` in `players`, noting the difficulty if it is the bot, how
+    /// many hints their side has used and whether they have been eliminated
+    fn player_label(&self, index: usize) -> String {
+        let player = &self.game.players()[index];
+        let color = self.game.color_of(index);
+        let mut label = if self.ai_color == Some(color) {
+            format!("{} ({} AI)", player.mention(), self.config.ai_difficulty)
+        } else {
+            player.mention().to_string()
+        };
+
+        let hints = self.hints_used[color_index(color)];
+        if hints > 0 {
+            label.push_str(&format!(" {}×{}", HINT, hints));
+        }
+        if self.config.time_control.is_some() {
+            let seconds = self.clocks[index].as_secs();
+            label.push_str(&format!(" {} `{}:{:02}`", CLOCK, seconds / 60, seconds % 60));
+        }
+        if self.config.variant == Variant::PopTen {
+            label.push_str(&format!(" ({}/{} collected)", self.game.collected(color), POP_TEN_TARGET));
+        }
+        if self.game.is_eliminated(index) {
+            let reason = match self.exits[index] {
+                Some(Exit::Resigned) => "resigned",
+                Some(Exit::TimedOut) => "timed out",
+                None => "out",
+            };
+            label = format!("~~{}~~ ({})", label, reason);
+        }
+        label
+    }
+
+    /// Mentions everyone playing the given colour
+    fn mention_color(&self, color: GameCell) -> String {
+        mention_list(
+            self.game.players()
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| self.game.color_of(i) == color)
+                .map(|(_, p)| p.id),
+        )
+    }
+
+    /// The move a number reaction stands for: a pop during the main phase of Pop 10, otherwise
+    /// a drop
+    fn reaction_move(&self, column: usize) -> Move {
+        if self.config.variant == Variant::PopTen && self.game.phase() == Phase::Play {
+            Move::Pop(column)
+        } else {
+            Move::Drop(column)
+        }
+    }
+
     fn display_board(&self) -> String {
+        // Label rows and columns when pieces are placed by coordinates
+        let labelled = self.config.variant == Variant::Anywhere;
+
         let mut s = String::new();
         // s.push_str("```\n");
-        for r in (0..BOARD_HEIGHT).rev() {
-            for c in 0..BOARD_WIDTH {
-                s.push(match self.board[c][r] {
-                    GameCell::Empty => BLANK_CELL,
-                    GameCell::Red => RED_PIECE,
-                    GameCell::Yellow => YELLOW_PIECE,
-                });
+        if labelled {
+            s.push(BOARD_CORNER);
+            for &label in &COLUMN_LABELS[..self.game.board().width()] {
+                s.push(label);
+                // Keep neighbouring letters from turning into a flag
+                s.push('\u{200b}');
+            }
+            s.push('\n');
+        }
+        for r in (0..self.game.board().height()).rev() {
+            if labelled {
+                s.push_str(NUMBER_EMOTES[r]);
+            }
+            if self.game.board().wraps() {
+                s.push(WRAP_EDGE);
+            }
+            for c in 0..self.game.board().width() {
+                if self.game.winning_line() & self.game.board().bit(c, r) != 0 {
+                    s.push(WINNING_PIECE);
+                } else {
+                    s.push_str(&self.game.board().get(c, r).to_string());
+                }
+            }
+            if self.game.board().wraps() {
+                s.push(WRAP_EDGE);
             }
             s.push('\n');
         }
@@ -227,196 +750,259 @@ impl GameState {
         s
     }
 
-    /// The current player places a piece in the specified column
-    /// 
-    /// Returns `MoveOutcome::Illegal` if the specified column is full
-    /// 
-    /// # Panic
-    /// Panics if `column` is outside of [0,BOARD_WIDTH)
-    fn play_move(&mut self, column: usize) -> MoveOutcome {
-        let color = if self.reds_turn { GameCell::Red } else { GameCell::Yellow };
-
-        let row = self.board[column].iter().take_while(|c| **c != GameCell::Empty).count();
-        if row >= 6 {
-            return MoveOutcome::Illegal;
+    /// Returns a record of the game, which has just finished with `result`
+    fn record(&self, result: GameResult) -> GameRecord {
+        GameRecord {
+            players: self.game.players().to_vec(),
+            guild: self.guild,
+            finished: chrono::Utc::now(),
+            config: self.config.clone(),
+            moves: self.game.moves().to_vec(),
+            eliminations: self.game.eliminations().to_vec(),
+            result,
         }
+    }
+}
 
-        self.board[column][row] = color;
+/// Where finished games are numbered and looked up. Each guild has its own numbering, and so does
+/// each DM channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecordScope {
+    Guild(GuildId),
+    Dm(ChannelId),
+}
 
-        let outcome = self.check_move(row, column);
-        
-        if outcome == MoveOutcome::Continue {
-            self.reds_turn = !self.reds_turn;
+impl RecordScope {
+    /// The scope of a message sent in `channel`, which is in `guild` unless it is a DM
+    pub fn new(guild: Option<GuildId>, channel: ChannelId) -> Self {
+        match guild {
+            Some(guild) => RecordScope::Guild(guild),
+            None => RecordScope::Dm(channel),
         }
-
-        outcome
     }
+}
 
-    /// Checks if the specified checker is part of a winning move
-    /// 
-    /// # Panic
-    /// Panics if the specified checker is out of bounds or empty
-    fn check_move(&self, row: usize, column: usize) -> MoveOutcome {
-        // Check the column for vertical victory
-        let vert_groups = self.board[column].iter().group_by(|&&c| c);
-        for (_, g) in vert_groups.into_iter() {
-            let g = g.collect::<SmallVec<[&GameCell; BOARD_HEIGHT]>>();
-            if *g[0] != GameCell::Empty && g.len() >= 4 {
-                return if *g[0] == GameCell::Red {
-                    MoveOutcome::RedWins
-                } else if *g[0] == GameCell::Yellow {
-                    MoveOutcome::YellowWins
-                } else {
-                    unreachable!()
-                }
-            }
-        }
+/// A finished game, kept so it can be analysed afterwards
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    /// Players in turn order, as in `GameState`
+    pub players: Vec<User>,
+    pub config: GameConfig,
+    /// The guild the game was played in, or `None` in DMs
+    pub guild: Option<GuildId>,
+    pub finished: chrono::DateTime<chrono::Utc>,
+    /// Moves played, in order. Red moves first unless the game started from a set position.
+    pub moves: Vec<Move>,
+    /// Players knocked out part way through a game of three or four
+    pub eliminations: Vec<Elimination>,
+    pub result: GameResult,
+}
 
-        // Check the row for horizontal victory
-        let horiz_groups = (0..7).into_iter()
-            .map(|i| self.board[i][row])
-            .group_by(|&c| c);
-        for (_, g) in horiz_groups.into_iter() {
-            let g = g.collect::<SmallVec<[GameCell; BOARD_WIDTH]>>();
-            if g[0] != GameCell::Empty && g.len() >= 4 {
-                return if g[0] == GameCell::Red {
-                    MoveOutcome::RedWins
-                } else if g[0] == GameCell::Yellow {
-                    MoveOutcome::YellowWins
-                } else {
-                    unreachable!()
-                }
-            }
-        }
+/// How a finished game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameResult {
+    /// The given colour completed a line, or collected enough pieces in Pop 10
+    Win(GameCell),
+    /// Everyone else resigned, leaving the given colour as the winner
+    Resignation(GameCell),
+    /// The other side ran out of time, leaving the given colour as the winner
+    Timeout(GameCell),
+    /// The board filled up
+    Draw,
+    /// The players agreed to a draw
+    AgreedDraw,
+}
 
-        // Check the northwest diagonal for victory
-        let nw_groups = (-4..4).into_iter()
-            .map(|i| 
-                if column as isize+i < 0 || row as isize+i < 0 {
-                    None
-                } else {
-                    self.board.get((column as isize+i) as usize)
-                        .and_then(|r| 
-                            r.get((row as isize+i) as usize))
-                        .copied()
-                })
-            .group_by(|&c| c);
-        for (_, g) in nw_groups.into_iter() {
-            let g = g.collect::<SmallVec<[Option<GameCell>; 8]>>();
-            if g[0] != Some(GameCell::Empty) && g[0] != None && g.len() >= 4 {
-                return if g[0] == Some(GameCell::Red) {
-                    MoveOutcome::RedWins
-                } else if g[0] == Some(GameCell::Yellow) {
-                    MoveOutcome::YellowWins
-                } else {
-                    unreachable!()
-                }
-            }
+impl std::fmt::Display for GameResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameResult::Win(color) => write!(f, "{} wins", color.name()),
+            GameResult::Resignation(color) => write!(f, "{} wins by resignation", color.name()),
+            GameResult::Timeout(color) => write!(f, "{} wins on time", color.name()),
+            GameResult::Draw => write!(f, "Draw"),
+            GameResult::AgreedDraw => write!(f, "Draw by agreement"),
         }
+    }
+}
 
-        // Check the northeast diagonal for victory
-        let ne_groups = (-4..4).into_iter()
-            .map(|i| 
-                if column as isize+i < 0 || row as isize-i < 0 {
-                    None
-                } else {
-                    self.board.get((column as isize+i) as usize)
-                        .and_then(|r| 
-                            r.get((row as isize-i) as usize))
-                        .copied()
-                })
-            .group_by(|&c| c);
-        for (_, g) in ne_groups.into_iter() {
-            let g = g.collect::<SmallVec<[Option<GameCell>; 8]>>();
-            if g[0] != Some(GameCell::Empty) && g[0] != None && g.len() >= 4 {
-                return if g[0] == Some(GameCell::Red) {
-                    MoveOutcome::RedWins
-                } else if g[0] == Some(GameCell::Yellow) {
-                    MoveOutcome::YellowWins
-                } else {
-                    unreachable!()
-                }
-            }
-        }
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameConfig {
+    /// Maximum time in-between moves before a game times out. Not used if there is a time
+    /// control.
+    pub move_timeout: Duration,
+    /// Each player's total thinking time for the game, if they have one
+    pub time_control: Option<TimeControl>,
+    /// Number of columns on the board
+    pub width: usize,
+    /// Number of rows on the board
+    pub height: usize,
+    /// Number of pieces in a row needed to win
+    pub win_length: usize,
+    pub variant: Variant,
+    /// How strongly the bot plays, if it is one of the players
+    pub ai_difficulty: ai::Difficulty,
+    /// Whether the game counts as competitive
+    pub rated: bool,
+    /// When players may ask for hints. The guild's policy takes precedence if it is stricter.
+    pub hints: HintPolicy,
+    /// Whether four players form two teams of two, with teammates sharing a colour
+    pub teams: bool,
+    /// The position to start from instead of an empty board
+    pub setup: Option<Board>,
+}
 
-        // Finally, check for a draw
-        if !self.board.iter().flatten().any(|&c| c == GameCell::Empty) {
-            return MoveOutcome::Draw
+impl GameConfig {
+    /// The rules of a game with this configuration
+    pub fn rules(&self) -> Rules {
+        Rules {
+            width: self.width,
+            height: self.height,
+            win_length: self.win_length,
+            variant: self.variant,
+            teams: self.teams,
+            setup: self.setup,
         }
+    }
 
-        MoveOutcome::Continue
+    /// Creates an empty board for a game with this configuration
+    pub fn new_board(&self) -> Board {
+        self.rules().new_board()
     }
-}
 
-#[derive(Debug)]
-struct GameConfig {
-    /// Maximum time in-between moves before a game times out.
-    move_timeout: std::time::Duration,
+    /// The board a game with this configuration starts on
+    pub fn start_board(&self) -> Board {
+        self.rules().start_board()
+    }
+
+    /// The colour that moves first
+    pub fn first_color(&self) -> GameCell {
+        self.rules().first_color()
+    }
 }
 
 impl Default for GameConfig {
     fn default() -> Self {
         GameConfig {
-            move_timeout: std::time::Duration::from_secs(120)
+            move_timeout: Duration::from_secs(120),
+            time_control: None,
+            width: DEFAULT_BOARD_WIDTH,
+            height: DEFAULT_BOARD_HEIGHT,
+            win_length: DEFAULT_WIN_LENGTH,
+            variant: Variant::default(),
+            ai_difficulty: ai::Difficulty::default(),
+            rated: false,
+            hints: HintPolicy::Allowed,
+            teams: false,
+            setup: None,
         }
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
-enum GameCell {
-    Empty, Red, Yellow
+/// A chess-style clock setting: a time bank for the whole game, plus a (Fischer) increment added
+/// after every move
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeControl {
+    pub initial: Duration,
+    pub increment: Duration,
 }
 
-impl std::fmt::Display for GameCell {
+impl std::fmt::Display for TimeControl {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", match self {
-            GameCell::Empty => BLANK_CELL,
-            GameCell::Red => RED_PIECE,
-            GameCell::Yellow => YELLOW_PIECE,
-        })
+        write!(f, "{}+{}", self.initial.as_secs() / 60, self.increment.as_secs())
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-enum MoveOutcome {
-    RedWins,
-    YellowWins,
-    Draw,
-    Continue,
-    Illegal,
+impl std::str::FromStr for TimeControl {
+    type Err = ();
+
+    /// Parses `<minutes>+<increment seconds>`, e.g. `3+2`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '+');
+        let minutes = parts.next().ok_or(())?.parse::<u64>().map_err(|_| ())?;
+        let increment = parts.next().ok_or(())?.parse::<u64>().map_err(|_| ())?;
+
+        if (1..=MAX_CLOCK_MINUTES).contains(&minutes) && increment <= MAX_INCREMENT_SECONDS {
+            Ok(TimeControl {
+                initial: Duration::from_secs(minutes * 60),
+                increment: Duration::from_secs(increment),
+            })
+        } else {
+            Err(())
+        }
+    }
 }
 
 /// An action that can be sent to a game thread
 pub enum GameAction {
-    /// Forcefully end the game with a draw
+    /// End the game with a draw that every player has agreed to
     ForceDraw,
+    /// Resign on behalf of the given user, if they are playing
+    Resign(User),
+    /// Ask the given user's opponents whether they will agree to a draw
+    OfferDraw(User),
+    /// Ask the given user's opponents whether they may take back their last move
+    Undo(User),
+    /// Take back the last move of the player at the given index, if the given number of moves
+    /// have been played (so that a takeback agreed to late doesn't undo a different move)
+    TakeBack(usize, usize),
+    /// Privately suggest a move to the given user if it is their turn
+    Hint(User),
+    /// Pop a piece out of a column on behalf of the given user, if it is their turn
+    Pop(User, usize),
+    /// Place a piece at the given column and row on behalf of the given user, if it is their turn
+    Place(User, usize, usize),
 }
 
-/// Handles a game in progress.
-async fn game(mut recv: tokio::sync::mpsc::Receiver<GameAction>, ctx: Context, channel: ChannelId, challenger: User, opponent: User) {
-    // This blocks but whatev
-    let (red_player, yellow_player) = if rand::random::<bool>() {
-        (challenger, opponent)
-    } else {
-        (opponent, challenger)
-    };
+/// Puts the players of a new game in a random turn order. In team games the first two players are
+/// one team and the last two are the other, and the teams take turns.
+fn turn_order(mut players: Vec<User>, teams: bool) -> Vec<User> {
+    let mut rng = rand::thread_rng();
+    if !teams {
+        players.shuffle(&mut rng);
+        return players;
+    }
 
-    let mut game_state = GameState {
-        config: GameConfig::default(),
+    let mut teams = [players.split_off(2), players];
+    teams.shuffle(&mut rng);
+    for team in &mut teams {
+        team.shuffle(&mut rng);
+    }
+    let [first, second] = teams;
+    first.into_iter().zip(second).flat_map(|(a, b)| vec![a, b]).collect()
+}
 
-        red_player, yellow_player,
-        reds_turn: true,
+/// Handles a game in progress. `players` starts with the challenger. `actions` sends to `recv`, for
+/// tasks the game starts that need to report back to it.
+async fn game(
+    mut recv: tokio::sync::mpsc::Receiver<GameAction>,
+    actions: tokio::sync::mpsc::Sender<GameAction>,
+    ctx: Context,
+    channel: ChannelId,
+    guild: Option<GuildId>,
+    players: Vec<User>,
+    config: GameConfig,
+) {
+    // Anything sent while the challenge was open, like a resignation or a request for a hint, was
+    // meant for a game that hadn't started yet, so it shouldn't count in this one
+    while recv.try_recv().is_ok() {}
 
-        board: [[GameCell::Empty; BOARD_HEIGHT]; BOARD_WIDTH],
-    };
+    let players = turn_order(players, config.teams);
+
+    let bot_id = ctx.cache.current_user_id().await;
+
+    let mut game_state = GameState::new(config, players);
+    game_state.guild = guild;
+    let bot_index = game_state.game.players().iter().position(|p| p.id == bot_id);
+    game_state.ai_color = bot_index.map(|i| game_state.game.color_of(i));
+    let board_width = game_state.game.board().width();
+    // Columns can't be picked by reaction when pieces go anywhere
+    let reaction_count = if game_state.config.variant == Variant::Anywhere { 0 } else { board_width };
 
     let board_message = channel.send_message(
         &ctx.http, 
         |msg|
             msg.content(game_state.message_content())
-                .reactions(NUMBER_EMOTES.iter().take(BOARD_WIDTH).map(|&s| {
-                    ReactionType::Unicode(String::from(s))
-                }))
+                .reactions(column_reactions(reaction_count))
     ).await;
 
     let mut board_message = match board_message {
@@ -424,196 +1010,746 @@ async fn game(mut recv: tokio::sync::mpsc::Receiver<GameAction>, ctx: Context, c
         Err(_) => return
     };
 
-    loop {
-        let current_player_id = game_state.current_player().id;
-        let react_watch = board_message
-            .await_reaction(&ctx.shard)
-            .author_id(current_player_id)
-            .filter(|r| {
-                if let ReactionType::Unicode(e) = &r.emoji {
-                    NUMBER_EMOTES[..BOARD_WIDTH].contains(&&e[..])
-                } else { false }
-            })
-            .timeout(game_state.config.move_timeout);
+    // Team games move too quickly through the rotation to follow, and the channel is free for
+    // teammates to talk in, so the next player is announced below the conversation
+    let mut announcement: Option<Message> = None;
 
-        tokio::select! {
-            act = recv.recv() => match act {
-                Some(GameAction::ForceDraw) | None => break, // Game forcefully closed prematurely
-            },
-            r = react_watch => match r {
-                None => { // Timeout
-                    let _ = board_message.edit(&ctx.http, |msg|
-                        msg.content(format!(
-                            "{}\n**Game over! {} forfeits. (timed out)**",
-                            game_state.message_content(),
-                            game_state.current_player().mention()
-                    ))).await;
+    'game: loop {
+        let current_player_id = game_state.game.current_player().id;
+        if game_state.config.teams {
+            if let Some(old) = announcement.take() {
+                let _ = old.delete(&ctx.http).await;
+            }
+            announcement = channel
+                .send_message(&ctx.http, |msg| {
+                    msg.content(format!(
+                        "{} {}, you're up!",
+                        game_state.game.current_color(),
+                        current_player_id.mention()
+                    ))
+                })
+                .await
+                .ok();
+        }
+        // Hints don't give players extra time to move
+        let turn_start = Instant::now();
+        let turn_deadline = turn_start + game_state.time_left();
 
-                    break
-                },
-                Some(r) => {
-                    let r = r.as_inner_ref();
-                    let emoji = match &r.emoji {
-                        ReactionType::Unicode(e) => e, _ => unreachable!()
+        let mut search = if game_state.ai_color == Some(game_state.game.current_color()) {
+            let board = *game_state.game.board();
+            let color = game_state.game.current_color();
+            let win_length = game_state.config.win_length;
+            let difficulty = game_state.config.ai_difficulty;
+            // Leave most of the clock for later moves
+            let time_limit = game_state.time_left() / BOT_CLOCK_SHARE;
+            Some(tokio::task::spawn_blocking(move || {
+                ai::choose_move_within(&board, color, win_length, difficulty, time_limit)
+            }))
+        } else {
+            None
+        };
+
+        // `None` if the player ran out of time
+        let mv = loop {
+            let act = if let Some(search) = &mut search {
+                tokio::select! {
+                    act = recv.recv() => act,
+                    col = search => match col {
+                        Ok(Some(col)) => break Some(Move::Drop(col)),
+                        // The game would have ended if there were no moves left
+                        Ok(None) => unreachable!(),
+                        Err(e) => {
+                            log::error!("AI search failed: {}", e);
+                            break 'game
+                        }
+                    },
+                    _ = tokio::time::delay_until(turn_deadline.into()) => break None,
+                }
+            } else {
+                let react_watch = await_column(
+                    &ctx,
+                    board_message.id,
+                    current_player_id,
+                    board_width,
+                    turn_deadline.saturating_duration_since(Instant::now()),
+                );
+
+                tokio::select! {
+                    act = recv.recv() => act,
+                    col = react_watch => match col {
+                        None => break None,
+                        Some(col) => break Some(game_state.reaction_move(col)),
+                    }
+                }
+            };
+
+            match act {
+                Some(GameAction::Hint(user)) => send_hint(&ctx, &mut game_state, &mut board_message, user).await,
+                Some(GameAction::Pop(user, column)) if user.id == current_player_id => break Some(Move::Pop(column)),
+                Some(GameAction::Place(user, column, row)) if user.id == current_player_id => break Some(Move::Place(column, row)),
+                Some(GameAction::Pop(..)) | Some(GameAction::Place(..)) => {},
+                Some(GameAction::Resign(user)) => {
+                    let index = match game_state.active_player(user.id) {
+                        Some(index) => index,
+                        None => continue,
                     };
-                    let col = NUMBER_EMOTES
-                        .iter()
-                        .position(|e| {
-                            e == &&emoji[..]
-                        })
-                        .unwrap();
-                    match game_state.play_move(col) {
-                        MoveOutcome::Continue | MoveOutcome::Illegal => {},
-                        winner => {
+                    let on_turn = index == game_state.game.turn();
+                    match game_state.game.resign(index) {
+                        MoveOutcome::Win(color) => {
+                            let result = format!(
+                                "**Game over! {} resigned, so {} {}!**",
+                                user.mention(),
+                                game_state.mention_color(color),
+                                if game_state.config.teams { "win" } else { "wins" }
+                            );
+                            finish_game(&ctx, &mut board_message, &game_state, GameResult::Resignation(color), result).await;
+                            break 'game
+                        }
+                        _ => {
+                            game_state.exits[index] = Some(Exit::Resigned);
                             let _ = board_message.edit(&ctx.http, |msg|
                                 msg.content(format!(
-                                    "{}\n{}",
+                                    "{}\n*{} resigned.*",
                                     game_state.message_content(),
-                                    match winner {
-                                        MoveOutcome::RedWins => format!("**Game over! {} wins!**", game_state.red_player.mention()),
-                                        MoveOutcome::YellowWins => format!("**Game over! {} wins!**", game_state.yellow_player.mention()),
-                                        MoveOutcome::Draw => format!("**Game over! Draw!**"),
-                                        _ => unreachable!()
-                                    }
+                                    user.mention()
                             ))).await;
-
-                            break
+                            if on_turn {
+                                continue 'game
+                            }
                         }
-                    };
+                    }
+                }
+                Some(GameAction::OfferDraw(user)) => offer_draw(&ctx, channel, &game_state, user, actions.clone()).await,
+                Some(GameAction::Undo(user)) => request_takeback(&ctx, channel, &game_state, user, actions.clone()).await,
+                Some(GameAction::TakeBack(index, move_count)) => {
+                    if game_state.game.moves().len() == move_count && game_state.game.take_back(index) {
+                        let _ = board_message.edit(&ctx.http, |msg|
+                            msg.content(format!(
+                                "{}\n*{} took back their last move.*",
+                                game_state.message_content(),
+                                game_state.game.current_player().mention()
+                        ))).await;
+                        continue 'game
+                    }
+                }
+                Some(GameAction::ForceDraw) => {
+                    let result = String::from("**Game over! Draw agreed.**");
+                    finish_game(&ctx, &mut board_message, &game_state, GameResult::AgreedDraw, result).await;
+                    break 'game
+                }
+                None => break 'game, // Game forcefully closed prematurely
+            }
+        };
 
-                    // TODO: Detect if `Manage Messages` is enabled
-                    let _ = r.delete(&ctx.http).await;
 
-                    board_message.edit(&ctx.http, |msg|
-                        msg.content(game_state.message_content())
-                    ).await.unwrap();
-                },
+        let mv = match mv {
+            Some(mv) => mv,
+            None => {
+                game_state.update_clock(game_state.game.turn(), turn_start.elapsed(), false);
+                // Running out of time counts as resigning. With more than two players left, the
+                // game carries on without them, but a team can't carry on a player short.
+                let player = game_state.game.current_player().mention();
+                let index = game_state.game.turn();
+                match game_state.game.resign(index) {
+                    MoveOutcome::Win(color) => {
+                        let result = format!(
+                            "**Game over! {} ran out of time, so {} {}!**",
+                            player,
+                            game_state.mention_color(color),
+                            if game_state.config.teams { "win" } else { "wins" }
+                        );
+                        finish_game(&ctx, &mut board_message, &game_state, GameResult::Timeout(color), result).await;
+                        break 'game
+                    }
+                    _ => {
+                        game_state.exits[index] = Some(Exit::TimedOut);
+                        let _ = board_message.edit(&ctx.http, |msg|
+                            msg.content(format!(
+                                "{}\n*{} timed out and has been eliminated.*",
+                                game_state.message_content(),
+                                player
+                        ))).await;
+                        continue 'game
+                    }
+                }
+            }
+        };
+
+        let mover = game_state.game.turn();
+        let outcome = game_state.game.play_move(mv);
+        game_state.update_clock(mover, turn_start.elapsed(), outcome != MoveOutcome::Illegal);
+
+        match outcome {
+            MoveOutcome::Continue | MoveOutcome::Illegal => {},
+            winner => {
+                let (result, text) = match winner {
+                    MoveOutcome::Win(color) => (GameResult::Win(color), format!(
+                        "**Game over! {} {}!**",
+                        game_state.mention_color(color),
+                        if game_state.config.teams { "win" } else { "wins" }
+                    )),
+                    MoveOutcome::Draw => (GameResult::Draw, String::from("**Game over! Draw!**")),
+                    MoveOutcome::Collected(color) => (GameResult::Win(color), format!(
+                        "**Game over! {} collected {} pieces and wins!**",
+                        game_state.mention_color(color),
+                        POP_TEN_TARGET
+                    )),
+                    _ => unreachable!()
+                };
+                finish_game(&ctx, &mut board_message, &game_state, result, text).await;
+
+                break
             }
         };
+
+        board_message.edit(&ctx.http, |msg|
+            msg.content(game_state.message_content())
+        ).await.unwrap();
+    }
+
+    if let Some(old) = announcement {
+        let _ = old.delete(&ctx.http).await;
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// Number reactions for picking one of the first `width` columns
+fn column_reactions(width: usize) -> impl Iterator<Item = ReactionType> {
+    NUMBER_EMOTES.iter().take(width).map(|&s| ReactionType::Unicode(String::from(s)))
+}
 
-    #[test]
-    fn horizontal_win() {
-        for color in &[GameCell::Red, GameCell::Yellow] {
-            for y in 0..BOARD_HEIGHT {
-                for x in 0..(BOARD_WIDTH-3) {
-                    let mut board = [[GameCell::Empty; BOARD_HEIGHT]; BOARD_WIDTH];
-                    for c in x..x+4 {
-                        board[c][y] = *color;
-                    }
+/// Waits for `player` to react to a message with one of the first `width` column numbers, then
+/// removes their reaction. Returns the column, or `None` if they haven't reacted within `timeout`.
+async fn await_column(ctx: &Context, message: MessageId, player: UserId, width: usize, timeout: Duration) -> Option<usize> {
+    let reaction = CollectReaction::new(&ctx.shard)
+        .message_id(message)
+        .author_id(player)
+        .filter(move |r| {
+            if let ReactionType::Unicode(e) = &r.emoji {
+                NUMBER_EMOTES[..width].contains(&&e[..])
+            } else { false }
+        })
+        .timeout(timeout)
+        .await?;
 
-                    let game = GameState {
-                        board,
-                        config: GameConfig::default(),
-                        red_player: User::default(),
-                        yellow_player: User::default(),
-                        reds_turn: true,
-                    };
+    let r = reaction.as_inner_ref();
+    let column = match &r.emoji {
+        ReactionType::Unicode(e) => NUMBER_EMOTES.iter().position(|n| n == e).unwrap(),
+        _ => unreachable!(),
+    };
 
-                    for c in x..x+4 {
-                        assert_eq!(game.check_move(y, c), if *color == GameCell::Yellow {
-                            MoveOutcome::YellowWins
-                        } else {
-                            MoveOutcome::RedWins
-                        });
-                    }
+    // TODO: Detect if `Manage Messages` is enabled
+    let _ = r.delete(&ctx.http).await;
+
+    Some(column)
+}
+
+/// Stores a finished game and writes `text` describing the result under the final board
+async fn finish_game(ctx: &Context, board_message: &mut Message, game_state: &GameState, result: GameResult, text: String) {
+    let scope = RecordScope::new(game_state.guild, board_message.channel_id);
+    let game_id = ctx.data.write().await
+        .get_mut::<GameModel>().unwrap()
+        .add_record(scope, game_state.record(result));
+
+    let footer = match game_id {
+        Some(id) => format!("*Game #{id}. Type `c4!analyze {id}` to review it, or `c4!export {id}` to download it.*", id = id),
+        None => String::from("*This game couldn't be saved.*"),
+    };
+    let _ = board_message.edit(&ctx.http, |msg|
+        msg.content(format!("{}\n{}\n{}", game_state.message_content(), text, footer))
+    ).await;
+}
+
+/// Asks the opponents of `user` to accept a draw by reaction
+async fn offer_draw(
+    ctx: &Context,
+    channel: ChannelId,
+    game_state: &GameState,
+    user: User,
+    actions: tokio::sync::mpsc::Sender<GameAction>,
+) {
+    let approvers = match game_state.active_player(user.id) {
+        Some(index) => game_state.opponents_of(index),
+        None => return,
+    };
+    if game_state.ai_color.is_some() {
+        let _ = channel
+            .send_message(ctx, |msg| {
+                msg.content(format!(
+                    "{} I don't accept draws. Type `c4!resign` if you want to end the game.",
+                    user.mention()
+                ))
+            })
+            .await;
+        return;
+    }
+
+    let request = format!("{} offers a draw.", user.mention());
+    propose(ctx, channel, request, approvers, GameAction::ForceDraw, actions).await
+}
+
+/// Asks the opponents of `user` to let them take back their last move. The bot always agrees.
+async fn request_takeback(
+    ctx: &Context,
+    channel: ChannelId,
+    game_state: &GameState,
+    user: User,
+    mut actions: tokio::sync::mpsc::Sender<GameAction>,
+) {
+    let index = match game_state.active_player(user.id) {
+        Some(index) => index,
+        None => return,
+    };
+    let refusal = if game_state.config.rated {
+        "Moves can't be taken back in rated games."
+    } else if !game_state.game.can_take_back(index) {
+        "You haven't made a move to take back."
+    } else {
+        let takeback = GameAction::TakeBack(index, game_state.game.moves().len());
+        if game_state.ai_color.is_some() {
+            let _ = actions.try_send(takeback);
+        } else {
+            let request = format!("{} would like to take back their last move.", user.mention());
+            propose(ctx, channel, request, game_state.opponents_of(index), takeback, actions).await;
+        }
+        return;
+    };
+
+    let _ = channel
+        .send_message(ctx, |msg| msg.content(format!("{} {}", user.mention(), refusal)))
+        .await;
+}
+
+/// Posts `request` and waits in the background for `approvers` to accept or decline it by reaction.
+/// If they all accept within a minute, `action` is sent to the game through `actions`.
+async fn propose(
+    ctx: &Context,
+    channel: ChannelId,
+    request: String,
+    approvers: Vec<UserId>,
+    action: GameAction,
+    mut actions: tokio::sync::mpsc::Sender<GameAction>,
+) {
+    let request_message = channel
+        .send_message(ctx, |msg| {
+            msg.content(format!(
+                "{} {}, react with {} to accept or {} to decline. This expires in 60 seconds.",
+                request,
+                mention_list(approvers.iter().copied()),
+                ACCEPT_CHALLENGE,
+                DENY_CHALLENGE
+            ))
+            .reactions([DENY_CHALLENGE, ACCEPT_CHALLENGE].iter().copied())
+        })
+        .await;
+    let mut request_message = match request_message {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
+
+    // Wait for the answer in the background so the game can carry on in the meantime
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let filter_ids = approvers.clone();
+        let mut reaction_stream = request_message
+            .await_reactions(&ctx.shard)
+            .filter(move |r| r.user_id.is_some_and(|id| filter_ids.contains(&id)))
+            .timeout(std::time::Duration::from_secs(60))
+            .await;
+
+        let mut accepted = HashSet::new();
+        let answer = loop {
+            let reaction = match reaction_stream.next().await {
+                Some(reaction) => reaction,
+                None => break String::from("*(expired)*"),
+            };
+            if !reaction.is_added() {
+                continue;
+            }
+            let r = reaction.as_inner_ref();
+            let user_id = match r.user_id {
+                Some(id) => id,
+                None => continue,
+            };
+            if r.emoji == ReactionType::from(DENY_CHALLENGE) {
+                break format!("*Declined by {}.*", user_id.mention());
+            } else if r.emoji == ReactionType::from(ACCEPT_CHALLENGE) {
+                accepted.insert(user_id);
+                if accepted.len() == approvers.len() {
+                    // This fails if the game has already finished, in which case there's nothing to do
+                    let _ = actions.try_send(action);
+                    break String::from("*Accepted.*");
                 }
             }
-        }
+        };
+        reaction_stream.stop();
+
+        let _ = request_message.edit(&ctx.http, |msg| msg.content(format!("{} {}", request, answer))).await;
+    });
+}
+
+/// Grades every move of a finished game and posts the results
+async fn analyze_game(ctx: Context, channel: ChannelId, game_id: usize, record: ImportedGame) {
+    let unavailable = if !record.config.variant.engine_support() {
+        Some(format!("Analysis isn't available for {} games.", record.config.variant))
+    } else if record.players.len() != 2 && !record.config.teams {
+        Some(String::from("Analysis is only available for two-player and team games."))
+    } else {
+        None
+    };
+    if let Some(reason) = unavailable {
+        let _ = channel.send_message(&ctx, |msg| msg.content(reason)).await;
+        return;
     }
 
-    #[test]
-    fn vertical_win() {
-        for color in &[GameCell::Red, GameCell::Yellow] {
-            for y in 0..(BOARD_HEIGHT-3) {
-                for x in 0..BOARD_WIDTH {
-                    let mut board = [[GameCell::Empty; BOARD_HEIGHT]; BOARD_WIDTH];
-                    for r in y..y+4 {
-                        board[x][r] = *color;
-                    }
+    let message = channel
+        .send_message(&ctx, |msg| msg.content(format!("*Analysing game #{}…*", game_id)))
+        .await;
+    let mut message = match message {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
 
-                    let game = GameState {
-                        board,
-                        config: GameConfig::default(),
-                        red_player: User::default(),
-                        yellow_player: User::default(),
-                        reds_turn: true,
-                    };
+    let GameConfig { width, height, win_length, .. } = record.config;
+    let board = record.config.start_board();
+    let first = record.config.first_color();
+    let moves = record
+        .moves
+        .iter()
+        .map(|&mv| match mv {
+            Move::Drop(column) => column,
+            Move::Pop(_) | Move::Place(..) => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    let analysis = match tokio::task::spawn_blocking(move || analysis::analyze(board, first, win_length, &moves)).await {
+        Ok(analysis) => analysis,
+        Err(e) => {
+            log::error!("Analysis of game #{} failed: {}", game_id, e);
+            return
+        }
+    };
 
-                    for r in y..y+4 {
-                        assert_eq!(game.check_move(r, x), if *color == GameCell::Yellow {
-                            MoveOutcome::YellowWins
-                        } else {
-                            MoveOutcome::RedWins
-                        });
-                    }
-                }
+    let _ = message.edit(&ctx.http, |msg| {
+        msg.content(format!(
+            "**Analysis of game #{}** ({}x{}, Connect {})\n{}",
+            game_id,
+            width,
+            height,
+            win_length,
+            // Teammates sit two apart in the turn order
+            analysis.render(
+                &mention_list(record.players.iter().step_by(2).flatten().copied()),
+                &mention_list(record.players.iter().skip(1).step_by(2).flatten().copied()),
+            ),
+        ))
+    })
+    .await;
+}
+
+/// Lets `user` try today's puzzle. They pick columns by reaction, and the bot answers each move
+/// with the strongest defence until they either win or play a move that doesn't win as quickly.
+async fn puzzle_session(ctx: Context, channel: ChannelId, user: User) {
+    let day = puzzle::today();
+    let (number, puzzle) = match puzzle::daily(day) {
+        Some(daily) => daily,
+        None => {
+            let _ = channel
+                .send_message(&ctx, |msg| msg.content(format!("{} There are no puzzles yet.", user.mention())))
+                .await;
+            return;
+        }
+    };
+
+    // Count the attempt straight away, so that leaving a puzzle unsolved doesn't keep the streak
+    let counted = ctx.data.write().await
+        .get_mut::<GameModel>().unwrap()
+        .start_puzzle(user.id, day);
+
+    let config = GameConfig { setup: Some(puzzle.board()), ..GameConfig::default() };
+    let mut game_state = GameState::new(config, vec![User::default(); 2]);
+    let color = puzzle.color();
+    let header = format!(
+        "**Daily puzzle #{}** {}\n{} {} to play and win in {}.",
+        number,
+        PUZZLE_STAR.to_string().repeat(puzzle.rating as usize),
+        color,
+        user.mention(),
+        puzzle.win_in
+    );
+    let content = |game_state: &GameState, status: &str| format!("{}\n{}\n\n{}", header, game_state.display_board(), status);
+
+    let message = channel
+        .send_message(&ctx, |msg| {
+            msg.content(content(&game_state, "*React with the column to play in.*"))
+                .reactions(column_reactions(game_state.game.board().width()))
+        })
+        .await;
+    let mut message = match message {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
+
+    let (solved, status) = loop {
+        let board = *game_state.game.board();
+        let winning = match tokio::task::spawn_blocking(move || puzzle::winning_columns(&board, color)).await {
+            Ok(Some(winning)) => winning,
+            Ok(None) => {
+                log::error!("Puzzle {} has no forced win", puzzle.moves);
+                return
+            }
+            Err(e) => {
+                log::error!("Puzzle search failed: {}", e);
+                return
+            }
+        };
+
+        let column = match await_column(&ctx, message.id, user.id, game_state.game.board().width(), PUZZLE_MOVE_TIMEOUT).await {
+            Some(column) => column,
+            None => {
+                let _ = message.edit(&ctx.http, |msg| msg.content(content(&game_state, "*Time's up.*"))).await;
+                return
             }
+        };
+
+        if !winning.contains(&column) {
+            let best = winning.iter().map(|c| (c + 1).to_string()).collect::<Vec<_>>().join(" or ");
+            break (false, format!("❌ Column {} doesn't win in time. The answer was column {}.", column + 1, best));
+        }
+        if let MoveOutcome::Win(_) = game_state.game.play_move(Move::Drop(column)) {
+            break (true, String::from("✅ Solved!"));
         }
-    }
 
-    #[test]
-    fn nw_diagonal_win() {
-        for color in &[GameCell::Red, GameCell::Yellow] {
-            for y in 0..(BOARD_HEIGHT-3) {
-                for x in 0..(BOARD_WIDTH-3) {
-                    let mut board = [[GameCell::Empty; BOARD_HEIGHT]; BOARD_WIDTH];
-                    for i in 0..4 {
-                        board[x+i][y+i] = *color;
-                    }
+        let board = *game_state.game.board();
+        let reply = match tokio::task::spawn_blocking(move || puzzle::defence(&board, color.other())).await {
+            Ok(Some(reply)) => reply,
+            _ => return,
+        };
+        game_state.game.play_move(Move::Drop(reply));
+        let status = format!("Good move! I replied in column {}. *What's next?*", reply + 1);
+        let _ = message.edit(&ctx.http, |msg| msg.content(content(&game_state, &status))).await;
+    };
 
-                    let game = GameState {
-                        board,
-                        config: GameConfig::default(),
-                        red_player: User::default(),
-                        yellow_player: User::default(),
-                        reds_turn: true,
-                    };
+    let streak = ctx.data.write().await
+        .get_mut::<GameModel>().unwrap()
+        .finish_puzzle(user.id, day, solved);
+    let streak = if counted {
+        format!("Streak: {} (best {})", streak.current(), streak.best)
+    } else {
+        String::from("*Only your first attempt each day counts towards your streak.*")
+    };
+    let _ = message
+        .edit(&ctx.http, |msg| msg.content(content(&game_state, &format!("{}\n{}", status, streak))))
+        .await;
+}
 
-                    for i in 0..4 {
-                        assert_eq!(game.check_move(y+i, x+i), if *color == GameCell::Yellow {
-                            MoveOutcome::YellowWins
-                        } else {
-                            MoveOutcome::RedWins
-                        });
-                    }
-                }
+/// Posts the starting position of a recorded game, which anyone can then step through with the
+/// replay controls
+async fn replay_game(ctx: Context, channel: ChannelId, title: String, game: ImportedGame) {
+    let ImportedGame { config, players, moves, eliminations } = game;
+    let players = players.len();
+    // Render every position up front, replaying the moves by the same rules as the game
+    let mut game_state = GameState::new(config, vec![User::default(); players]);
+    let mut frames = vec![(String::from("Start"), game_state.display_board())];
+    let mut eliminations = eliminations.into_iter().peekable();
+    for i in 0..=moves.len() {
+        // Knock players out in between the same moves as they were in the game
+        while let Some(elimination) = eliminations.next_if(|e| e.after_moves == i) {
+            let game = &mut game_state.game;
+            if elimination.player >= players || game.is_eliminated(elimination.player) || game.players_left() <= 2 {
+                let _ = channel
+                    .send_message(&ctx, |msg| {
+                        msg.content(format!("The players knocked out of {} don't add up, so it can't be replayed.", title))
+                    })
+                    .await;
+                return;
             }
+            let color = game.color_of(elimination.player);
+            game.eliminate(elimination.player);
+            frames.last_mut().unwrap().0.push_str(&format!(" ({} is out)", color));
+        }
+
+        let mv = match moves.get(i) {
+            Some(&mv) => mv,
+            None => break,
+        };
+        let color = game_state.game.current_color();
+        if game_state.game.play_move(mv) == MoveOutcome::Illegal {
+            let _ = channel
+                .send_message(&ctx, |msg| msg.content(format!("Move {} of {} is illegal, so it can't be replayed.", i + 1, title)))
+                .await;
+            return;
         }
+        let caption = format!("Move {}/{}: {} `{}`", i + 1, moves.len(), color, mv);
+        frames.push((caption, game_state.display_board()));
     }
 
-    #[test]
-    fn ne_diagonal_win() {
-        for color in &[GameCell::Red, GameCell::Yellow] {
-            for y in 0..(BOARD_HEIGHT-3) {
-                for x in 0..(BOARD_WIDTH-3) {
-                    let mut board = [[GameCell::Empty; BOARD_HEIGHT]; BOARD_WIDTH];
-                    for i in 0..4 {
-                        board[x+i][y+(3-i)] = *color;
-                    }
+    let content = |frame: usize| {
+        let (caption, board) = &frames[frame];
+        format!(
+            "**Replay of {}** (Connect {}, {})\n{}\n{}",
+            title, game_state.config.win_length, game_state.config.variant, caption, board
+        )
+    };
+    let message = channel
+        .send_message(&ctx, |msg| {
+            msg.content(content(0))
+                .reactions(REPLAY_CONTROLS.iter().map(|&s| ReactionType::Unicode(String::from(s))))
+        })
+        .await;
+    let mut message = match message {
+        Ok(msg) => msg,
+        Err(_) => return,
+    };
 
-                    let game = GameState {
-                        board,
-                        config: GameConfig::default(),
-                        red_player: User::default(),
-                        yellow_player: User::default(),
-                        reds_turn: true,
-                    };
+    let bot_id = ctx.cache.current_user_id().await;
+    let mut frame: usize = 0;
+    loop {
+        let reaction = message
+            .await_reaction(&ctx.shard)
+            .filter(move |r| {
+                if let ReactionType::Unicode(e) = &r.emoji {
+                    r.user_id != Some(bot_id) && REPLAY_CONTROLS.contains(&&e[..])
+                } else { false }
+            })
+            .timeout(REPLAY_TIMEOUT)
+            .await;
+        let reaction = match reaction {
+            Some(reaction) => reaction,
+            None => break,
+        };
+        let r = reaction.as_inner_ref();
+        let control = match &r.emoji {
+            ReactionType::Unicode(e) => REPLAY_CONTROLS.iter().position(|c| c == e).unwrap(),
+            _ => unreachable!(),
+        };
+        frame = match control {
+            0 => 0,
+            1 => frame.saturating_sub(1),
+            2 => (frame + 1).min(frames.len() - 1),
+            _ => frames.len() - 1,
+        };
+        let _ = r.delete(&ctx.http).await;
 
-                    for i in 0..4 {
-                        assert_eq!(game.check_move(y+(3-i), x+i), if *color == GameCell::Yellow {
-                            MoveOutcome::YellowWins
-                        } else {
-                            MoveOutcome::RedWins
-                        });
-                    }
+        let _ = message.edit(&ctx.http, |msg| msg.content(content(frame))).await;
+    }
+}
+
+/// Privately suggests a move to `user` if it is their turn and hints are allowed in this game. The
+/// search runs in the background, so the game carries on in the meantime.
+async fn send_hint(ctx: &Context, game_state: &mut GameState, board_message: &mut Message, user: User) {
+    let refusal = if user.id != game_state.game.current_player().id {
+        String::from("You can only ask for a hint on your turn.")
+    } else if !game_state.config.variant.engine_support() {
+        format!("Hints aren't available for {} games.", game_state.config.variant)
+    } else if game_state.game.colors().len() != 2 {
+        String::from("Hints are only available in two-player and team games.")
+    } else if !game_state.config.hints.permits(game_state.config.rated) {
+        String::from("Hints aren't allowed in this game.")
+    } else {
+        let board = *game_state.game.board();
+        let color = game_state.game.current_color();
+        let win_length = game_state.config.win_length;
+        // Count the hint now, since the game may have moved on by the time the search finishes
+        game_state.hints_used[color_index(color)] += 1;
+        let _ = board_message.edit(&ctx.http, |msg| msg.content(game_state.message_content())).await;
+
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            let reply = match tokio::task::spawn_blocking(move || hint::suggest(&board, color, win_length)).await {
+                Ok(Some(hint)) => format!("{} Play column {}: {}.", HINT, hint.column + 1, hint.reason),
+                Ok(None) => return,
+                Err(e) => {
+                    log::error!("Hint search failed: {}", e);
+                    return
                 }
+            };
+            if let Err(e) = user.direct_message(&ctx, |msg| msg.content(reply)).await {
+                log::warn!("Couldn't send hint to {}: {}", user.id, e);
+            }
+        });
+        return;
+    };
+
+    if let Err(e) = user.direct_message(ctx, |msg| msg.content(refusal)).await {
+        log::warn!("Couldn't send hint to {}: {}", user.id, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winning_line_is_highlighted() {
+        let mut game_state = GameState::new(GameConfig::default(), vec![User::default(); 2]);
+        for c in 0..3 {
+            assert_eq!(game_state.game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+            assert_eq!(game_state.game.play_move(Move::Drop(c)), MoveOutcome::Continue);
+        }
+        assert!(!game_state.display_board().contains(WINNING_PIECE));
+        assert_eq!(game_state.game.play_move(Move::Drop(3)), MoveOutcome::Win(GameCell::Red));
+        assert_eq!(game_state.display_board().matches(WINNING_PIECE).count(), 4);
+    }
+
+    #[test]
+    fn reactions_pop_during_pop_ten() {
+        let config = GameConfig { variant: Variant::PopTen, ..GameConfig::default() };
+        let mut game_state = GameState::new(config, vec![User::default(); 2]);
+        assert_eq!(game_state.reaction_move(2), Move::Drop(2));
+        for c in 0..7 {
+            for _ in 0..6 {
+                game_state.game.play_move(Move::Drop(c));
             }
         }
+        assert_eq!(game_state.reaction_move(2), Move::Pop(2));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn clock_runs_down_and_gains_increment() {
+        assert_eq!("0+5".parse::<TimeControl>(), Err(()));
+        assert_eq!("3".parse::<TimeControl>(), Err(()));
+        let time_control = "3+2".parse::<TimeControl>().unwrap();
+        assert_eq!(time_control.to_string(), "3+2");
+
+        let config = GameConfig { time_control: Some(time_control), ..GameConfig::default() };
+        let mut game = GameState::new(config, vec![User::default(); 2]);
+        assert_eq!(game.time_left(), Duration::from_secs(180));
+
+        game.update_clock(0, Duration::from_secs(10), true);
+        assert_eq!(game.clocks, [Duration::from_secs(172), Duration::from_secs(180)]);
+        // An illegal move doesn't earn the increment
+        game.update_clock(0, Duration::from_secs(10), false);
+        assert_eq!(game.time_left(), Duration::from_secs(162));
+        game.update_clock(1, Duration::from_secs(500), false);
+        assert_eq!(game.clocks[1], Duration::default());
+    }
+
+    #[test]
+    fn eliminated_players_show_why() {
+        let mut game = GameState::new(GameConfig::default(), vec![User::default(); 4]);
+        assert_eq!(game.game.resign(0), MoveOutcome::Continue);
+        game.exits[0] = Some(Exit::Resigned);
+        assert_eq!(game.game.resign(1), MoveOutcome::Continue);
+        game.exits[1] = Some(Exit::TimedOut);
+
+        assert!(game.player_label(0).ends_with("~~ (resigned)"));
+        assert!(game.player_label(1).ends_with("~~ (timed out)"));
+        assert!(!game.player_label(2).contains("~~"));
+    }
+
+    #[test]
+    fn guild_policy_overrides_looser_config() {
+        let mut model = GameModel::new();
+        let guild = GuildId(1);
+        let config = GameConfig::default();
+
+        assert_eq!(model.apply_guild_settings(None, config.clone()).hints, HintPolicy::Allowed);
+        assert_eq!(model.apply_guild_settings(Some(guild), config.clone()).hints, HintPolicy::CasualOnly);
+
+        model.guild_settings.insert(guild, GuildSettings { hints: HintPolicy::Disabled });
+        assert_eq!(model.apply_guild_settings(Some(guild), config.clone()).hints, HintPolicy::Disabled);
+
+        model.guild_settings.insert(guild, GuildSettings { hints: HintPolicy::Allowed });
+        let strict = GameConfig { hints: HintPolicy::Disabled, ..config };
+        assert_eq!(model.apply_guild_settings(Some(guild), strict).hints, HintPolicy::Disabled);
+    }
+}
@@ -0,0 +1,144 @@
+//! Move suggestions for players who ask for help during a game.
+
+use four_in_a_row_core::ai::{self, Difficulty};
+use four_in_a_row_core::board::{Board, GameCell};
+use four_in_a_row_core::solver::{self, Position};
+use std::time::{Duration, Instant};
+
+/// How long the solver may spend on a hint before falling back to a regular search
+const SOLVER_TIME_BUDGET: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hint {
+    pub column: usize,
+    pub reason: Reason,
+}
+
+/// Why a column was suggested
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    WinsImmediately,
+    /// The opponent would complete a line of this length there on their next move
+    BlocksOpponent(usize),
+    /// The solver proved the player to move can win in this many of their own moves
+    SolverWin(u32),
+    SolverDraw,
+    /// The solver proved the player to move will lose after this many moves of the opponent
+    SolverLoss(u32),
+    /// The best move the computer found by searching a few moves ahead
+    Search,
+    /// Like `Search`, but the solver was tried first and ran out of time, so this may not be the
+    /// best move
+    SolverTimedOut,
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::WinsImmediately => write!(f, "wins immediately"),
+            Reason::BlocksOpponent(length) => write!(f, "blocks opponent's {}", line_name(*length)),
+            Reason::SolverWin(moves) => write!(f, "solver says win in {}", moves),
+            Reason::SolverDraw => write!(f, "solver says best play leads to a draw"),
+            Reason::SolverLoss(moves) => write!(f, "solver says loss in {}, but this holds out longest", moves),
+            Reason::Search => write!(f, "looks strongest after searching a few moves ahead"),
+            Reason::SolverTimedOut => write!(
+                f,
+                "the solver ran out of time, so this is a best guess from searching a few moves ahead"
+            ),
+        }
+    }
+}
+
+/// Names a line of `length` pieces, e.g. "four"
+fn line_name(length: usize) -> String {
+    const NAMES: &[&str] = &["zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine"];
+    NAMES
+        .get(length)
+        .map(|name| String::from(*name))
+        .unwrap_or_else(|| format!("line of {}", length))
+}
+
+/// Suggests a column for `color` to play in, or returns `None` if the board is full
+///
+/// This blocks for several seconds if the position has to be searched.
+pub fn suggest(board: &Board, color: GameCell, win_length: usize) -> Option<Hint> {
+    if let Some(column) = winning_column(board, color, win_length) {
+        return Some(Hint { column, reason: Reason::WinsImmediately });
+    }
+    if let Some(column) = winning_column(board, color.other(), win_length) {
+        return Some(Hint { column, reason: Reason::BlocksOpponent(win_length) });
+    }
+
+    let mut reason = Reason::Search;
+    if solver::supports(board, win_length) {
+        if let Some(position) = Position::from_board(board, color) {
+            let best = solver::with_solver(|solver| {
+                solver.set_deadline(Some(Instant::now() + SOLVER_TIME_BUDGET));
+                solver.best_move(&position)
+            });
+            if let Some((column, score)) = best {
+                let reason = match position.moves_to_win(score) {
+                    None => Reason::SolverDraw,
+                    Some(moves) if score > 0 => Reason::SolverWin(moves),
+                    Some(moves) => Reason::SolverLoss(moves),
+                };
+                return Some(Hint { column, reason });
+            }
+            reason = Reason::SolverTimedOut;
+        }
+    }
+
+    ai::choose_move(board, color, win_length, Difficulty::Hard).map(|column| Hint { column, reason })
+}
+
+/// Returns a column where `color` would complete a line straight away
+fn winning_column(board: &Board, color: GameCell, win_length: usize) -> Option<usize> {
+    (0..board.width()).find(|&column| {
+        let mut next = *board;
+        match next.drop_piece(column, color) {
+            Some(row) => next.lines_through(column, row, win_length) != 0,
+            None => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    #[test]
+    fn prefers_winning_over_blocking() {
+        let mut board = Board::new(7, 6);
+        for c in 0..3 {
+            board.drop_piece(c, GameCell::Red);
+            board.drop_piece(c, GameCell::Yellow);
+        }
+        let hint = suggest(&board, GameCell::Red, 4);
+        assert_eq!(hint, Some(Hint { column: 3, reason: Reason::WinsImmediately }));
+        let hint = suggest(&board, GameCell::Yellow, 4);
+        assert_eq!(hint, Some(Hint { column: 3, reason: Reason::BlocksOpponent(4) }));
+    }
+
+    #[test]
+    fn uses_solver_on_standard_board() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut board = Board::new(7, 6);
+        let mut color = GameCell::Red;
+
+        // Play randomly into the late middlegame, where the solver is quick
+        while board.pieces(GameCell::Red).count_ones() < 13 {
+            let column = rng.gen_range(0, 7);
+            let mut next = board;
+            if let Some(row) = next.drop_piece(column, color) {
+                if next.lines_through(column, row, 4) == 0 && winning_column(&next, color.other(), 4).is_none() {
+                    board = next;
+                    color = color.other();
+                }
+            }
+        }
+
+        let hint = suggest(&board, color, 4).unwrap();
+        assert_ne!(hint.reason, Reason::Search);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod analysis;
+pub mod command;
+pub mod event_handler;
+pub mod game;
+pub mod hint;
+pub mod notation;
+pub mod puzzle;
+pub mod settings;
+pub mod storage;
@@ -1,7 +1,4 @@
-mod command;
-mod event_handler;
-mod game;
-
+use four_in_a_row::{event_handler, game};
 use serenity::client::bridge::gateway::GatewayIntents;
 
 #[tokio::main]
@@ -0,0 +1,439 @@
+//! Move notation, and the plain text format finished games are exported in.
+//!
+//! A game's moves are written one after another with no separators. A drop is the column number
+//! counted from 1, so the standard Connect 4 notation `4453` is understood by other tools. PopOut
+//! pops are the column number prefixed with `p`, and pieces placed anywhere are written as a
+//! column letter and a row number, e.g. `c4`.
+//!
+//! Positions can also be written out row by row, from top to bottom, separated by `/`. Each cell
+//! is `r` or `y` for a piece and `.` when empty, e.g. `......./......./...r.../...yr..`
+//! for a 7 column board. Rows that are left out at the top are empty.
+
+use crate::game::{GameConfig, GameRecord};
+use four_in_a_row_core::board::{Board, GameCell, PLAYER_COLORS};
+use four_in_a_row_core::game::{self, Elimination, Move};
+use serenity::model::id::UserId;
+use std::time::Duration;
+
+/// Writes out a sequence of moves in notation
+pub fn move_string(moves: &[Move]) -> String {
+    moves.iter().map(Move::to_string).collect()
+}
+
+/// Reads a sequence of moves in notation, returning `None` if it is malformed
+pub fn parse_moves(s: &str) -> Option<Vec<Move>> {
+    let mut moves = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        let mv = match c {
+            '1'..='9' => Move::Drop(c.to_digit(10)? as usize - 1),
+            'p' => Move::Pop(chars.next()?.to_digit(10)?.checked_sub(1)? as usize),
+            'a'..='i' => {
+                let row = chars.next()?.to_digit(10)?.checked_sub(1)? as usize;
+                Move::Place((c as u8 - b'a') as usize, row)
+            }
+            _ => return None,
+        };
+        moves.push(mv);
+    }
+    Some(moves)
+}
+
+/// Reasons a position can't be played from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionError {
+    /// The string isn't a move string or a board string
+    Malformed,
+    /// A move goes in a column that doesn't exist or is full
+    IllegalMove(usize),
+    /// A board string has the wrong number of cells in a row, or too many rows
+    WrongSize,
+    /// The piece at the given column and row has an empty cell below it
+    FloatingPiece(usize, usize),
+    /// Red doesn't have the same number of pieces as yellow or one more
+    PieceCount,
+    /// Someone has already completed a line
+    AlreadyWon,
+    /// There is nowhere left to move
+    Full,
+}
+
+impl std::fmt::Display for PositionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            PositionError::Malformed => write!(f, "it isn't a move string or rows separated by `/`"),
+            PositionError::IllegalMove(n) => write!(f, "move {} doesn't fit on the board", n),
+            PositionError::WrongSize => write!(f, "it doesn't match the size of the board"),
+            PositionError::FloatingPiece(column, row) => {
+                write!(f, "the piece at {} is floating", Move::Place(column, row))
+            }
+            PositionError::PieceCount => write!(f, "red must have as many pieces as yellow, or one more"),
+            PositionError::AlreadyWon => write!(f, "someone has already won"),
+            PositionError::Full => write!(f, "the board is full"),
+        }
+    }
+}
+
+/// Parses a position for a game with the given configuration, either as a move string of drops
+/// starting with red or as a board string. The position must be reachable in a normal game that
+/// hasn't finished yet.
+pub fn parse_position(s: &str, config: &GameConfig) -> Result<Board, PositionError> {
+    let board = if s.chars().all(|c| c.is_ascii_digit()) {
+        let mut board = config.new_board();
+        let mut color = GameCell::Red;
+        for (i, c) in s.chars().enumerate() {
+            let column = match c.to_digit(10).unwrap() as usize {
+                n @ 1..=9 if n <= board.width() => n - 1,
+                _ => return Err(PositionError::IllegalMove(i + 1)),
+            };
+            let row = board.drop_piece(column, color).ok_or(PositionError::IllegalMove(i + 1))?;
+            if board.lines_through(column, row, config.win_length) != 0 {
+                return Err(PositionError::AlreadyWon);
+            }
+            color = color.other();
+        }
+        board
+    } else if s.contains('/') {
+        parse_board_string(s, config)?
+    } else {
+        return Err(PositionError::Malformed);
+    };
+
+    if board.is_full() {
+        return Err(PositionError::Full);
+    }
+    Ok(board)
+}
+
+/// Parses and checks a position written out row by row
+fn parse_board_string(s: &str, config: &GameConfig) -> Result<Board, PositionError> {
+    let mut board = config.new_board();
+    let rows = s.split('/').collect::<Vec<_>>();
+    if rows.len() > board.height() {
+        return Err(PositionError::WrongSize);
+    }
+    for (row, cells) in rows.iter().rev().enumerate() {
+        if cells.chars().count() != board.width() {
+            return Err(PositionError::WrongSize);
+        }
+        for (column, c) in cells.chars().enumerate() {
+            let cell = match c.to_ascii_lowercase() {
+                '.' => continue,
+                'r' => GameCell::Red,
+                'y' => GameCell::Yellow,
+                _ => return Err(PositionError::Malformed),
+            };
+            board.place_piece(column, row, cell);
+        }
+    }
+
+    for column in 0..board.width() {
+        for row in 1..board.height() {
+            if board.get(column, row) != GameCell::Empty && board.get(column, row - 1) == GameCell::Empty {
+                return Err(PositionError::FloatingPiece(column, row));
+            }
+        }
+    }
+
+    let red = board.pieces(GameCell::Red).count_ones();
+    let yellow = board.pieces(GameCell::Yellow).count_ones();
+    if red != yellow && red != yellow + 1 {
+        return Err(PositionError::PieceCount);
+    }
+
+    if PLAYER_COLORS.iter().any(|&color| board.lines_of(color, config.win_length) != 0) {
+        return Err(PositionError::AlreadyWon);
+    }
+    Ok(board)
+}
+
+/// Writes out a board row by row, in the format `parse_position` accepts
+pub fn board_string(board: &Board) -> String {
+    (0..board.height())
+        .rev()
+        .map(|row| {
+            (0..board.width())
+                .map(|column| match board.get(column, row) {
+                    GameCell::Empty => '.',
+                    GameCell::Red => 'r',
+                    GameCell::Yellow => 'y',
+                    GameCell::Green => 'g',
+                    GameCell::Blue => 'b',
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A game read back from the format written by `export`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportedGame {
+    pub config: GameConfig,
+    /// Players in turn order, with their IDs if the record has them
+    pub players: Vec<Option<UserId>>,
+    pub moves: Vec<Move>,
+    pub eliminations: Vec<Elimination>,
+}
+
+/// Reads a game in the format written by `export`, returning `None` if it is malformed. Every move
+/// must be on the board, but whether it is legal isn't checked, since that needs the rules of the
+/// game.
+pub fn import(text: &str) -> Option<ImportedGame> {
+    let mut config = GameConfig::default();
+    let mut colors = Vec::new();
+    let mut players = Vec::new();
+    let mut setup = None;
+    let mut eliminated = None;
+    let mut moves = None;
+
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let tag = match line.strip_prefix('[').and_then(|tag| tag.strip_suffix(']')) {
+            Some(tag) => tag,
+            None => {
+                moves = Some(parse_moves(line)?);
+                continue;
+            }
+        };
+        let (key, value) = tag.split_at(tag.find(' ')?);
+        let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+
+        match key {
+            "Board" => {
+                let (width, height) = value.split_at(value.find('x')?);
+                config.width = width.parse().ok()?;
+                config.height = height[1..].parse().ok()?;
+            }
+            "Connect" => config.win_length = value.parse().ok()?,
+            "Variant" => config.variant = value.parse().ok()?,
+            "TimeControl" => match value.strip_suffix("s per move") {
+                Some(seconds) => config.move_timeout = Duration::from_secs(seconds.parse().ok()?),
+                None => config.time_control = Some(value.parse().ok()?),
+            },
+            "Setup" => setup = Some(value),
+            "Eliminated" => eliminated = Some(value),
+            key => {
+                if let Some(&color) = PLAYER_COLORS.iter().find(|color| color.name() == key) {
+                    colors.push(color);
+                    // Written as the player's tag followed by their ID in brackets
+                    let id = value.rsplit_once(" (").and_then(|(_, id)| id.strip_suffix(')')?.parse().ok());
+                    players.push(id.map(UserId));
+                }
+            }
+        }
+    }
+
+    // Teammates are listed under the same colour
+    config.teams = colors.len() == 4 && colors[0] == colors[2];
+    // The same rules as for challenges, so that a record can't set up a game nobody could play
+    config.rules().check(colors.len()).ok()?;
+    if !colors.iter().enumerate().all(|(i, &color)| color == game::player_color(i, config.teams)) {
+        return None;
+    }
+    if let Some(setup) = setup {
+        config.setup = Some(parse_position(setup, &config).ok()?);
+        config.rules().check(colors.len()).ok()?;
+    }
+    let moves = moves.unwrap_or_default();
+    let on_board = |mv: &Move| match *mv {
+        Move::Drop(column) | Move::Pop(column) => column < config.width,
+        Move::Place(column, row) => column < config.width && row < config.height,
+    };
+    if !moves.iter().all(on_board) {
+        return None;
+    }
+
+    // Written as e.g. `Blue after 12, Red after 20`. Only games without teams have eliminations,
+    // so each colour is a single player.
+    let mut eliminations = Vec::new();
+    for elimination in eliminated.into_iter().flat_map(|value| value.split(", ")) {
+        let (color, after_moves) = elimination.split_once(" after ")?;
+        let player = PLAYER_COLORS[..colors.len()].iter().position(|c| c.name() == color)?;
+        let after_moves = after_moves.parse().ok()?;
+        let in_order = eliminations.last().is_none_or(|last: &Elimination| last.after_moves <= after_moves);
+        if config.teams || after_moves > moves.len() || !in_order {
+            return None;
+        }
+        eliminations.push(Elimination { player, after_moves });
+    }
+
+    Some(ImportedGame {
+        config,
+        players,
+        moves,
+        eliminations,
+    })
+}
+
+/// Writes out a finished game as a header block of `[Key "value"]` tags followed by its moves
+pub fn export(game_id: usize, record: &GameRecord) -> String {
+    let config = &record.config;
+    let mut tags = vec![
+        ("Game", game_id.to_string()),
+        ("Date", record.finished.format("%Y-%m-%d").to_string()),
+        ("Guild", record.guild.map_or_else(|| String::from("DM"), |guild| guild.to_string())),
+    ];
+    for (i, player) in record.players.iter().enumerate() {
+        let color = game::player_color(i, config.teams);
+        tags.push((color.name(), format!("{} ({})", player.tag(), player.id)));
+    }
+    tags.push(("Variant", config.variant.to_string()));
+    tags.push(("Board", format!("{}x{}", config.width, config.height)));
+    tags.push(("Connect", config.win_length.to_string()));
+    tags.push(("TimeControl", match config.time_control {
+        Some(time_control) => time_control.to_string(),
+        None => format!("{}s per move", config.move_timeout.as_secs()),
+    }));
+    if let Some(board) = &config.setup {
+        tags.push(("Setup", board_string(board)));
+    }
+    if !record.eliminations.is_empty() {
+        let eliminations = record
+            .eliminations
+            .iter()
+            .map(|e| format!("{} after {}", game::player_color(e.player, config.teams).name(), e.after_moves))
+            .collect::<Vec<_>>();
+        tags.push(("Eliminated", eliminations.join(", ")));
+    }
+    tags.push(("Result", record.result.to_string()));
+
+    let mut s = String::new();
+    for (key, value) in tags {
+        s.push_str(&format!("[{} \"{}\"]\n", key, value));
+    }
+    s.push('\n');
+    s.push_str(&move_string(&record.moves));
+    s.push('\n');
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use four_in_a_row_core::board::GameCell;
+    use crate::game::{GameConfig, GameResult};
+    use chrono::TimeZone;
+    use serenity::model::prelude::*;
+
+    #[test]
+    fn export_lists_tags_then_moves() {
+        let mut players = vec![User::default(); 2];
+        players[1].id = UserId(7);
+        let record = GameRecord {
+            players,
+            guild: Some(GuildId(42)),
+            finished: chrono::Utc.ymd(2021, 3, 14).and_hms(15, 9, 26),
+            config: GameConfig::default(),
+            moves: vec![Move::Drop(3), Move::Drop(3), Move::Pop(4), Move::Place(2, 3)],
+            eliminations: Vec::new(),
+            result: GameResult::Resignation(GameCell::Yellow),
+        };
+
+        let export = export(12, &record);
+        let mut lines = export.lines();
+        assert_eq!(lines.next(), Some("[Game \"12\"]"));
+        assert_eq!(lines.next(), Some("[Date \"2021-03-14\"]"));
+        assert_eq!(lines.next(), Some("[Guild \"42\"]"));
+        assert!(lines.next().unwrap().starts_with("[Red "));
+        assert!(lines.next().unwrap().ends_with("(7)\"]"));
+        assert!(export.contains("[TimeControl \"120s per move\"]\n"));
+        assert!(export.contains("[Result \"Yellow wins by resignation\"]\n"));
+        assert!(export.ends_with("\n\n44p5c4\n"));
+    }
+
+    #[test]
+    fn exported_games_can_be_imported() {
+        let mut config = GameConfig {
+            width: 8,
+            variant: game::Variant::Toroidal,
+            teams: true,
+            time_control: "5+3".parse().ok(),
+            ..GameConfig::default()
+        };
+        config.setup = Some(parse_position("45", &config).unwrap());
+        let record = GameRecord {
+            players: vec![User::default(); 4],
+            guild: None,
+            finished: chrono::Utc::now(),
+            config: config.clone(),
+            moves: vec![Move::Drop(7), Move::Pop(0), Move::Place(7, 5)],
+            eliminations: Vec::new(),
+            result: GameResult::AgreedDraw,
+        };
+
+        let game = import(&export(1, &record)).unwrap();
+        let players = vec![Some(User::default().id); 4];
+        assert_eq!(game, ImportedGame { config, players, moves: record.moves, eliminations: Vec::new() });
+        assert_eq!(import("[Board \"7x6\"]\n\n4453"), None);
+        assert_eq!(import("[Red \"a\"]\n[Yellow \"b\"]\n\n44x"), None);
+        // Off the side and top of a 7x6 board
+        assert_eq!(import("[Red \"a\"]\n[Yellow \"b\"]\n\n448"), None);
+        assert_eq!(import("[Red \"a\"]\n[Yellow \"b\"]\n\n44c7"), None);
+        let game = import("[Red \"a\"]\n[Yellow \"b#0001 (7)\"]\n\n44").unwrap();
+        assert_eq!(game.players, vec![None, Some(UserId(7))]);
+    }
+
+    #[test]
+    fn unplayable_rules_are_rejected() {
+        let two = "[Red \"a\"]\n[Yellow \"b\"]\n";
+        let three = "[Red \"a\"]\n[Yellow \"b\"]\n[Green \"c\"]\n";
+        assert!(import(&format!("{}[Board \"4x6\"]\n[Connect \"6\"]\n\n11", two)).is_some());
+        assert!(import(&format!("{}[Variant \"Misère\"]\n\n11", two)).is_some());
+
+        // Longer than the board in both directions
+        assert_eq!(import(&format!("{}[Connect \"8\"]\n\n11", two)), None);
+        assert_eq!(import(&format!("{}[Variant \"Misère\"]\n\n11", three)), None);
+        // A teammate without a team of four
+        assert_eq!(import("[Red \"a\"]\n[Yellow \"b\"]\n[Red \"c\"]\n\n11"), None);
+        // Lines would wrap around onto themselves
+        assert_eq!(import(&format!("{}[Board \"4x6\"]\n[Variant \"Toroidal\"]\n[Connect \"6\"]\n\n11", two)), None);
+        assert_eq!(import(&format!("{}[Setup \"44\"]\n\n11", three)), None);
+        assert_eq!(import(&format!("{}[Board \"4x4\"]\n[Variant \"Pop 10\"]\n\n11", two)), None);
+    }
+
+    #[test]
+    fn eliminations_are_exported() {
+        let eliminations = vec![
+            Elimination { player: 3, after_moves: 2 },
+            Elimination { player: 0, after_moves: 3 },
+        ];
+        let record = GameRecord {
+            players: vec![User::default(); 4],
+            guild: None,
+            finished: chrono::Utc::now(),
+            config: GameConfig::default(),
+            moves: vec![Move::Drop(3), Move::Drop(3), Move::Drop(2), Move::Drop(4)],
+            eliminations: eliminations.clone(),
+            result: GameResult::Timeout(GameCell::Green),
+        };
+
+        let export = export(1, &record);
+        assert!(export.contains("[Eliminated \"Blue after 2, Red after 3\"]\n"));
+        assert_eq!(import(&export).unwrap().eliminations, eliminations);
+        // After more moves than were played
+        let export = export.replace("Red after 3", "Red after 5");
+        assert_eq!(import(&export), None);
+    }
+
+    #[test]
+    fn move_and_board_strings_agree() {
+        let config = GameConfig::default();
+        let board = parse_position("4453", &config).unwrap();
+        assert_eq!(board_string(&board), "......./......./......./......./...y.../..yrr..");
+        assert_eq!(parse_position(&board_string(&board), &config), Ok(board));
+        assert_eq!(parse_position("...y.../..yrr..", &config), Ok(board));
+    }
+
+    #[test]
+    fn illegal_positions_are_rejected() {
+        let config = GameConfig::default();
+        assert_eq!(parse_position("448", &config), Err(PositionError::IllegalMove(3)));
+        assert_eq!(parse_position("4444444", &config), Err(PositionError::IllegalMove(7)));
+        assert_eq!(parse_position("1212121", &config), Err(PositionError::AlreadyWon));
+        assert_eq!(parse_position("4x53", &config), Err(PositionError::Malformed));
+        assert_eq!(parse_position("...r.../.......", &config), Err(PositionError::FloatingPiece(3, 1)));
+        assert_eq!(parse_position("......./..rrr..", &config), Err(PositionError::PieceCount));
+        assert_eq!(parse_position("yyy..../rrrr...", &config), Err(PositionError::AlreadyWon));
+        assert_eq!(parse_position("......../.......", &config), Err(PositionError::WrongSize));
+    }
+}
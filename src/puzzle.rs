@@ -0,0 +1,245 @@
+//! Daily puzzles: positions on the standard board where the player to move can force a win.
+
+use four_in_a_row_core::board::{Board, GameCell};
+use four_in_a_row_core::solver::{self, Position, Solver};
+
+/// One puzzle per line: the moves leading to the position, how many moves the winner needs and a
+/// difficulty rating from 1 to 5. Lines starting with `#` are comments.
+const PUZZLES: &str = include_str!("../data/puzzles.txt");
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Puzzle {
+    /// Columns played to reach the position, numbered from 1, e.g. `4453`
+    pub moves: String,
+    /// Number of moves the player to move needs to win, counting the winning move itself
+    pub win_in: u32,
+    /// How hard the puzzle is, from 1 to 5, as worked out by `rating`
+    pub rating: u32,
+}
+
+impl Puzzle {
+    /// Parses a line of the puzzle file, returning `None` for comments and malformed lines
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_ascii_whitespace();
+        let moves = fields.next().filter(|moves| !moves.starts_with('#'))?;
+        Position::from_moves(moves)?;
+        Some(Puzzle {
+            moves: String::from(moves),
+            win_in: fields.next()?.parse().ok()?,
+            rating: fields.next()?.parse().ok()?,
+        })
+    }
+
+    /// The puzzle's position on a standard board
+    pub fn board(&self) -> Board {
+        let mut board = Board::new(solver::WIDTH, solver::HEIGHT);
+        let mut color = GameCell::Red;
+        for c in self.moves.chars() {
+            board.drop_piece(c.to_digit(10).unwrap() as usize - 1, color);
+            color = color.other();
+        }
+        board
+    }
+
+    /// The colour of the player solving the puzzle
+    pub fn color(&self) -> GameCell {
+        if self.moves.len().is_multiple_of(2) {
+            GameCell::Red
+        } else {
+            GameCell::Yellow
+        }
+    }
+}
+
+impl std::fmt::Display for Puzzle {
+    /// Writes the puzzle as a line of the puzzle file
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.moves, self.win_in, self.rating)
+    }
+}
+
+/// Rates how hard the puzzle reached by `moves` is, from 1 to 5, or returns `None` if it isn't a
+/// forced win or the solver runs out of time
+///
+/// The rating follows the line the bot defends with. It goes up for every turn where only one
+/// column wins as quickly as possible, and for every column that wins, but too slowly to solve the
+/// puzzle, since those are the moves that look right but aren't.
+pub fn rating(solver: &mut Solver, moves: &str) -> Option<u32> {
+    let mut position = Position::from_moves(moves)?;
+    let mut points = 0;
+    loop {
+        let scores = solver.analyze(&position)?;
+        let best = scores.iter().flatten().copied().max().filter(|&best| best > 0)?;
+        let fastest = (0..solver::WIDTH).filter(|&c| scores[c] == Some(best)).collect::<Vec<_>>();
+        let slower = scores.iter().flatten().filter(|&&score| score > 0 && score < best).count();
+        points += (fastest.len() == 1) as u32 + slower as u32;
+
+        if position.is_winning_move(fastest[0]) {
+            break;
+        }
+        position.play_column(fastest[0]);
+        let (reply, _) = solver.best_move(&position)?;
+        position.play_column(reply);
+    }
+
+    Some(match points {
+        0..=2 => 1,
+        3..=5 => 2,
+        6..=8 => 3,
+        9..=13 => 4,
+        _ => 5,
+    })
+}
+
+/// Every puzzle the bot knows, in the order they are handed out
+pub fn all() -> Vec<Puzzle> {
+    PUZZLES.lines().filter_map(Puzzle::parse).collect()
+}
+
+/// Returns the number and puzzle for the given day, counted from the Unix epoch. Puzzles repeat
+/// once they run out.
+pub fn daily(day: i64) -> Option<(usize, Puzzle)> {
+    let puzzles = all();
+    if puzzles.is_empty() {
+        return None;
+    }
+    let number = day.rem_euclid(puzzles.len() as i64) as usize;
+    Some((number + 1, puzzles[number].clone()))
+}
+
+/// Today's day number, counted from the Unix epoch in UTC
+pub fn today() -> i64 {
+    chrono::Utc::now().timestamp().div_euclid(24 * 60 * 60)
+}
+
+/// Returns the columns that win for `color` as quickly as possible, or `None` if there is no
+/// forced win
+///
+/// This blocks while the solver runs, which is quick for puzzle positions.
+pub fn winning_columns(board: &Board, color: GameCell) -> Option<Vec<usize>> {
+    let position = Position::from_board(board, color)?;
+    let scores = solver::with_solver(|solver| solver.analyze(&position))?;
+    let best = scores.iter().flatten().copied().max().filter(|&best| best > 0)?;
+    Some((0..solver::WIDTH).filter(|&c| scores[c] == Some(best)).collect())
+}
+
+/// Returns the move that holds out longest for `color`, who is losing the puzzle
+///
+/// This blocks while the solver runs.
+pub fn defence(board: &Board, color: GameCell) -> Option<usize> {
+    let position = Position::from_board(board, color)?;
+    solver::with_solver(|solver| solver.best_move(&position)).map(|(column, _)| column)
+}
+
+/// A player's run of daily puzzles solved on consecutive days
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Streak {
+    /// Number of consecutive days solved, up to and including `last_solved`
+    run: u32,
+    pub best: u32,
+    last_attempt: Option<i64>,
+    last_solved: Option<i64>,
+}
+
+impl Streak {
+    /// Number of consecutive days solved. An attempt that wasn't solved ends the streak.
+    pub fn current(&self) -> u32 {
+        if self.last_attempt == self.last_solved { self.run } else { 0 }
+    }
+
+    /// Starts an attempt at the puzzle for `day`, which counts as a failure unless `solve` is
+    /// called for it. Only the first attempt each day counts, and this returns false for any
+    /// after that.
+    pub fn attempt(&mut self, day: i64) -> bool {
+        if self.last_attempt == Some(day) {
+            return false;
+        }
+        self.last_attempt = Some(day);
+        true
+    }
+
+    /// Marks the attempt at the puzzle for `day` as solved
+    pub fn solve(&mut self, day: i64) {
+        if self.last_attempt != Some(day) || self.last_solved == Some(day) {
+            return;
+        }
+        self.run = if self.last_solved == Some(day - 1) { self.run + 1 } else { 1 };
+        self.best = self.best.max(self.run);
+        self.last_solved = Some(day);
+    }
+
+    /// Parses a line written by `Display`, returning `None` if it is malformed
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split_ascii_whitespace();
+        let mut day = || match fields.next()? {
+            "-" => Some(None),
+            day => day.parse().ok().map(Some),
+        };
+        let (last_attempt, last_solved) = (day()?, day()?);
+        Some(Streak {
+            run: fields.next()?.parse().ok()?,
+            best: fields.next()?.parse().ok()?,
+            last_attempt,
+            last_solved,
+        })
+    }
+}
+
+impl std::fmt::Display for Streak {
+    /// Writes the days of the last attempt and last solve (or `-` for none), then the run and the
+    /// best run
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let day = |day: Option<i64>| day.map_or_else(|| String::from("-"), |day| day.to_string());
+        write!(f, "{} {} {} {}", day(self.last_attempt), day(self.last_solved), self.run, self.best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn puzzles_are_forced_wins() {
+        let puzzles = all();
+        assert!(!puzzles.is_empty());
+        for puzzle in puzzles {
+            let board = puzzle.board();
+            let position = Position::from_board(&board, puzzle.color()).unwrap();
+            let (_, score) = solver::with_solver(|solver| solver.best_move(&position)).unwrap();
+            assert_eq!(position.moves_to_win(score), Some(puzzle.win_in), "{}", puzzle.moves);
+            assert!(score > 0, "{}", puzzle.moves);
+            let rating = solver::with_solver(|solver| rating(solver, &puzzle.moves));
+            assert_eq!(rating, Some(puzzle.rating), "{}", puzzle.moves);
+            assert_eq!(Puzzle::parse(&puzzle.to_string()), Some(puzzle));
+        }
+    }
+
+    #[test]
+    fn streak_counts_consecutive_days() {
+        let mut streak = Streak::default();
+        assert!(streak.attempt(10));
+        streak.solve(10);
+        assert!(!streak.attempt(10));
+        assert!(streak.attempt(11));
+        streak.solve(11);
+        assert_eq!((streak.current(), streak.best), (2, 2));
+
+        // Missing a day starts again
+        assert!(streak.attempt(13));
+        streak.solve(13);
+        assert_eq!((streak.current(), streak.best), (1, 2));
+
+        // An attempt counts as failed until it is solved
+        assert!(streak.attempt(14));
+        assert_eq!((streak.current(), streak.best), (0, 2));
+        streak.solve(14);
+        assert_eq!((streak.current(), streak.best), (2, 2));
+        assert!(streak.attempt(15));
+        assert!(streak.attempt(16));
+        streak.solve(16);
+        assert_eq!((streak.current(), streak.best), (1, 2));
+
+        assert_eq!(Streak::parse(&streak.to_string()), Some(streak));
+        assert_eq!(Streak::parse(&Streak::default().to_string()), Some(Streak::default()));
+    }
+}
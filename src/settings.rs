@@ -0,0 +1,141 @@
+//! Per-guild settings that moderators can change.
+
+use serenity::model::prelude::*;
+use serenity::prelude::*;
+
+/// When players are allowed to ask for hints. Variants are ordered from most to least restrictive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HintPolicy {
+    Disabled,
+    /// Hints may only be used in games that aren't rated
+    CasualOnly,
+    Allowed,
+}
+
+impl HintPolicy {
+    /// Returns true if hints may be used in a game with this policy
+    pub fn permits(self, rated: bool) -> bool {
+        match self {
+            HintPolicy::Disabled => false,
+            HintPolicy::CasualOnly => !rated,
+            HintPolicy::Allowed => true,
+        }
+    }
+
+    /// The word that selects this policy in commands and settings files
+    pub fn keyword(self) -> &'static str {
+        match self {
+            HintPolicy::Disabled => "disabled",
+            HintPolicy::CasualOnly => "casual",
+            HintPolicy::Allowed => "allowed",
+        }
+    }
+}
+
+impl std::fmt::Display for HintPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            HintPolicy::Disabled => "disabled",
+            HintPolicy::CasualOnly => "casual games only",
+            HintPolicy::Allowed => "allowed",
+        })
+    }
+}
+
+impl std::str::FromStr for HintPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_ascii_lowercase()[..] {
+            "disabled" | "off" => Ok(HintPolicy::Disabled),
+            "casual" => Ok(HintPolicy::CasualOnly),
+            "allowed" | "on" => Ok(HintPolicy::Allowed),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Settings chosen by a guild's moderators, which apply to every game played there
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuildSettings {
+    pub hints: HintPolicy,
+}
+
+impl Default for GuildSettings {
+    fn default() -> Self {
+        GuildSettings {
+            hints: HintPolicy::CasualOnly,
+        }
+    }
+}
+
+impl GuildSettings {
+    /// Reads settings written by `Display`. Unknown settings are skipped and missing ones keep their
+    /// defaults, but `None` is returned if a known setting has an invalid value.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut settings = GuildSettings::default();
+        for field in line.split_ascii_whitespace() {
+            if let Some(("hints", policy)) = field.split_once(':') {
+                settings.hints = policy.parse().ok()?;
+            }
+        }
+        Some(settings)
+    }
+}
+
+impl std::fmt::Display for GuildSettings {
+    /// Writes each setting as `name:value`, separated by spaces
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hints:{}", self.hints.keyword())
+    }
+}
+
+/// A change to a guild's settings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Setting {
+    Hints(HintPolicy),
+}
+
+impl Setting {
+    pub fn apply(&self, settings: &mut GuildSettings) {
+        match *self {
+            Setting::Hints(policy) => settings.hints = policy,
+        }
+    }
+}
+
+impl std::fmt::Display for Setting {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Setting::Hints(policy) => write!(f, "Hints are now {}.", policy),
+        }
+    }
+}
+
+/// Returns true if `user` may change the settings of `guild`, i.e. they own it or have the
+/// `Manage Server` permission
+pub async fn can_manage_guild(ctx: &Context, guild: GuildId, user: UserId) -> bool {
+    let partial_guild = match ctx.http.get_guild(guild.0).await {
+        Ok(g) => g,
+        Err(_) => return false,
+    };
+    if partial_guild.owner_id == user {
+        return true;
+    }
+
+    let member = match ctx.http.get_member(guild.0, user.0).await {
+        Ok(m) => m,
+        Err(_) => return false,
+    };
+
+    // The @everyone role shares its ID with the guild
+    let everyone = RoleId(guild.0);
+    let permissions = member
+        .roles
+        .iter()
+        .chain(std::iter::once(&everyone))
+        .filter_map(|id| partial_guild.roles.get(id))
+        .fold(Permissions::empty(), |p, role| p | role.permissions);
+
+    permissions.administrator() || permissions.manage_guild()
+}
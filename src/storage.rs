@@ -0,0 +1,213 @@
+//! Files that keep finished games and puzzle streaks around when the bot restarts.
+//!
+//! Everything is kept under one directory, `storage` unless `STORAGE_DIR` says otherwise. Each
+//! finished game is written in the format of `notation::export` to `games/<guild>/<id>.txt`, or
+//! `games/dm-<channel>/<id>.txt` for games played in DMs. Streaks are kept in `streaks.txt`, one
+//! player per line, and the settings chosen by each guild's moderators in `settings.txt`, one guild
+//! per line.
+
+use crate::game::{GameRecord, RecordScope};
+use crate::notation;
+use crate::puzzle::Streak;
+use crate::settings::GuildSettings;
+use serenity::model::id::{GuildId, UserId};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::PathBuf;
+
+/// Environment variable naming the directory to store files in
+const STORAGE_DIR_VAR: &str = "STORAGE_DIR";
+const DEFAULT_STORAGE_DIR: &str = "storage";
+const STREAKS_FILE: &str = "streaks.txt";
+const SETTINGS_FILE: &str = "settings.txt";
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    root: PathBuf,
+}
+
+impl Default for Storage {
+    /// Storage in the directory given by `STORAGE_DIR`, or `storage` in the working directory
+    fn default() -> Self {
+        let root = std::env::var_os(STORAGE_DIR_VAR).unwrap_or_else(|| DEFAULT_STORAGE_DIR.into());
+        Storage::new(root)
+    }
+}
+
+impl Storage {
+    /// Storage in the directory `root`, which is created when something is first written to it
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Storage { root: root.into() }
+    }
+
+    /// The directory holding the games of `scope`
+    fn games_dir(&self, scope: RecordScope) -> PathBuf {
+        let name = match scope {
+            RecordScope::Guild(guild) => guild.to_string(),
+            RecordScope::Dm(channel) => format!("dm-{}", channel),
+        };
+        self.root.join("games").join(name)
+    }
+
+    /// Writes out a finished game and returns the ID it was given, which is one more than the
+    /// highest ID in `scope` so far
+    pub fn add_game(&self, scope: RecordScope, record: &GameRecord) -> io::Result<usize> {
+        let dir = self.games_dir(scope);
+        fs::create_dir_all(&dir)?;
+        let highest = fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok()?.file_name().to_str()?.strip_suffix(".txt")?.parse::<usize>().ok())
+            .max()
+            .unwrap_or(0);
+
+        // Never overwrite a game, even if another one was written in the meantime
+        let mut game_id = highest + 1;
+        loop {
+            let file = OpenOptions::new().write(true).create_new(true).open(dir.join(format!("{}.txt", game_id)));
+            match file {
+                Ok(mut file) => {
+                    file.write_all(notation::export(game_id, record).as_bytes())?;
+                    return Ok(game_id);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => game_id += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads back a finished game in the format written by `notation::export`, or `None` if
+    /// `scope` has no game with that ID
+    pub fn game(&self, scope: RecordScope, game_id: usize) -> Option<String> {
+        fs::read_to_string(self.games_dir(scope).join(format!("{}.txt", game_id))).ok()
+    }
+
+    /// Reads every player's puzzle streak, skipping any lines that are malformed
+    pub fn streaks(&self) -> HashMap<UserId, Streak> {
+        let text = fs::read_to_string(self.root.join(STREAKS_FILE)).unwrap_or_default();
+        text.lines()
+            .filter_map(|line| {
+                let (player, streak) = line.split_once(' ')?;
+                Some((UserId(player.parse().ok()?), Streak::parse(streak)?))
+            })
+            .collect()
+    }
+
+    /// Writes out every player's puzzle streak, replacing the ones stored before
+    pub fn save_streaks(&self, streaks: &HashMap<UserId, Streak>) -> io::Result<()> {
+        let text = streaks
+            .iter()
+            .map(|(player, streak)| format!("{} {}\n", player, streak))
+            .collect::<String>();
+        self.replace_file(STREAKS_FILE, &text)
+    }
+
+    /// Reads the settings of every guild that has changed them, skipping any lines that are
+    /// malformed
+    pub fn guild_settings(&self) -> HashMap<GuildId, GuildSettings> {
+        let text = fs::read_to_string(self.root.join(SETTINGS_FILE)).unwrap_or_default();
+        text.lines()
+            .filter_map(|line| {
+                let (guild, settings) = line.split_once(' ')?;
+                Some((GuildId(guild.parse().ok()?), GuildSettings::parse(settings)?))
+            })
+            .collect()
+    }
+
+    /// Writes out the settings of every guild, replacing the ones stored before
+    pub fn save_guild_settings(&self, settings: &HashMap<GuildId, GuildSettings>) -> io::Result<()> {
+        let text = settings
+            .iter()
+            .map(|(guild, settings)| format!("{} {}\n", guild, settings))
+            .collect::<String>();
+        self.replace_file(SETTINGS_FILE, &text)
+    }
+
+    /// Replaces the contents of the file `name` with `text`
+    fn replace_file(&self, name: &str, text: &str) -> io::Result<()> {
+        fs::create_dir_all(&self.root)?;
+        // Write the new file alongside the old one first, so a crash can't leave it half written
+        let temp = self.root.join(format!("{}.new", name));
+        fs::write(&temp, text)?;
+        fs::rename(temp, self.root.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameConfig, GameResult};
+    use four_in_a_row_core::game::Move;
+    use serenity::model::prelude::*;
+
+    #[test]
+    fn games_are_numbered_per_scope() {
+        let root = std::env::temp_dir().join(format!("four_in_a_row_storage_{}", std::process::id()));
+        let storage = Storage::new(&root);
+        let record = GameRecord {
+            players: vec![User::default(); 2],
+            guild: Some(GuildId(1)),
+            finished: chrono::Utc::now(),
+            config: GameConfig::default(),
+            moves: vec![Move::Drop(3), Move::Drop(3)],
+            eliminations: Vec::new(),
+            result: GameResult::AgreedDraw,
+        };
+
+        let guild = RecordScope::Guild(GuildId(1));
+        let dm = RecordScope::Dm(ChannelId(2));
+        assert_eq!(storage.add_game(guild, &record).unwrap(), 1);
+        assert_eq!(storage.add_game(guild, &record).unwrap(), 2);
+        assert_eq!(storage.add_game(dm, &record).unwrap(), 1);
+
+        let text = storage.game(guild, 2).unwrap();
+        assert_eq!(text, notation::export(2, &record));
+        assert_eq!(notation::import(&text).unwrap().moves, record.moves);
+        assert_eq!(storage.game(guild, 3), None);
+        assert_eq!(storage.game(RecordScope::Guild(GuildId(2)), 1), None);
+
+        // Numbering carries on where it left off after a restart
+        assert_eq!(Storage::new(&root).add_game(guild, &record).unwrap(), 3);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn streaks_are_saved() {
+        let root = std::env::temp_dir().join(format!("four_in_a_row_streaks_{}", std::process::id()));
+        let storage = Storage::new(&root);
+        assert!(storage.streaks().is_empty());
+
+        let mut streak = Streak::default();
+        streak.attempt(10);
+        streak.solve(10);
+        let streaks = vec![(UserId(1), streak), (UserId(2), Streak::default())].into_iter().collect();
+        storage.save_streaks(&streaks).unwrap();
+        assert_eq!(storage.streaks(), streaks);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn guild_settings_are_saved() {
+        use crate::settings::HintPolicy;
+
+        let root = std::env::temp_dir().join(format!("four_in_a_row_settings_{}", std::process::id()));
+        let storage = Storage::new(&root);
+        assert!(storage.guild_settings().is_empty());
+
+        let settings = vec![
+            (GuildId(1), GuildSettings { hints: HintPolicy::Disabled }),
+            (GuildId(2), GuildSettings { hints: HintPolicy::Allowed }),
+            (GuildId(3), GuildSettings::default()),
+        ]
+        .into_iter()
+        .collect();
+        storage.save_guild_settings(&settings).unwrap();
+        assert_eq!(Storage::new(&root).guild_settings(), settings);
+
+        // Lines with unknown guilds or policies are skipped
+        fs::write(root.join(SETTINGS_FILE), "4 hints:sometimes\nfive hints:allowed\n6 hints:disabled\n").unwrap();
+        let read = storage.guild_settings();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[&GuildId(6)].hints, HintPolicy::Disabled);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}